@@ -10,24 +10,63 @@ fn main() {
 
             heartbeat_interval: 100,
             timeout: 10000,
+            handshake_timeout: 2000,
+            pause_timeout: None,
+            require_handshake_ack: false,
+            stall_fraction: None,
             ping_memory_length: 16,
+            min_ping_samples: None,
+            ping_smoothing: None,
+
+            ack_delay: None,
+            piggyback_acks: false,
 
             listen: false,
+            challenge_handshake: false,
+
+            strict_peers: false,
+            duplicate_instances: None,
+            expected_connections: 0,
+
+            max_packets_per_update: None,
+            fair_queuing: None,
+            low_water_mark: None,
+
+            report_invalid_packets: false,
+            sequenced_messages: false,
+            report_gaps: false,
+
+            receive_batch_size: 1,
+            recv_socket_buffer: None,
+
+            seed: None,
+
+            checksum: false,
+
+            reuse_address: false,
+            reuse_port: false,
+            bind_device: None,
 
             channels: vec![
-                ChannelConfig::SendUnreliable,
+                ChannelConfig::SendUnreliable {
+                    queue_limit: 1,
+                    rate_limit: None,
+                },
                 ChannelConfig::ReceiveUnreliable,
 
                 ChannelConfig::SendReliable {
-                    resend_threshhold: 1.25
+                    resend_threshhold: 1.25,
+                    adaptive_resend: None,
+                    max_retransmits: None,
+                    rate_limit: None,
+                    lifetime: None,
                 },
                 ChannelConfig::ReceiveReliable,
             ],
-        }
+        },
+        "10.0.20.248:3000".parse().unwrap()
     ).unwrap();
 
-    client.connect("10.0.20.248:3000".parse().unwrap()).unwrap();
-
     let mut last_ping = Instant::now();
 
     loop {
@@ -38,17 +77,16 @@ fn main() {
                 Event::Message(addr, channel_id, message) => {
                     println!("message from {} on channel {} {:?}", addr, channel_id, message);
 
-                    client.disconnect_all();
+                    client.disconnect().unwrap();
                 },
+                _ => (),
             }
         }
 
         if last_ping.elapsed().as_millis() > 3000 {
-            client.send_single(2, "Ping".as_bytes()).unwrap();
+            client.send(2, "Ping".as_bytes()).unwrap();
             last_ping = Instant::now();
-            for i in client.connections() {
-                println!("current ping for {} is {:?}", i, client.get_ping(i).unwrap());
-            }
+            println!("current ping is {:?}", client.ping().unwrap());
         }
     }
 }
\ No newline at end of file
@@ -11,22 +11,57 @@ fn main() {
 
             heartbeat_interval: 100,
             timeout: 10000,
+            handshake_timeout: 2000,
+            pause_timeout: None,
+            require_handshake_ack: false,
+            stall_fraction: None,
             ping_memory_length: 16,
+            min_ping_samples: None,
+            ping_smoothing: None,
+
+            ack_delay: None,
+            piggyback_acks: false,
 
             listen: false,
+            challenge_handshake: false,
+
+            strict_peers: false,
+            duplicate_instances: None,
+            expected_connections: 0,
+
+            max_packets_per_update: None,
+            fair_queuing: None,
+            low_water_mark: None,
+
+            report_invalid_packets: false,
+            sequenced_messages: false,
+            report_gaps: false,
+
+            receive_batch_size: 1,
+            recv_socket_buffer: None,
+
+            seed: None,
+
+            checksum: false,
+
+            reuse_address: false,
+            reuse_port: false,
+            bind_device: None,
 
             channels: vec![
                 ChannelConfig::SendFecReliable {
                     resend_threshhold: 1.25,
+                    adaptive_resend: None,
+                    max_retransmits: None,
+                    rate_limit: None,
                     max_data_symbols: 4,
                     max_repair_symbols: 3,
                 },
             ],
-        }
+        },
+        "10.176.82.194:3001".parse().unwrap()
     ).unwrap();
 
-    client.connect("10.176.82.194:3001".parse().unwrap()).unwrap();
-
     let mut last_ping = Instant::now();
 
     loop {
@@ -34,13 +69,13 @@ fn main() {
             match event {
                 Event::Connection(addr) => println!("connection {}", addr),
                 Event::Disconnection(addr, reason) => println!("disconnected {} {:?}", addr, reason),
-                Event::Message(_, _, _) => (),
+                _ => (),
             }
         }
 
 
         if last_ping.elapsed().as_millis() > 4000 {
-            client.send_single(0, "this is an fec ping".as_bytes()).unwrap();
+            client.send(0, "this is an fec ping".as_bytes()).unwrap();
             println!("pinged");
 
             last_ping = Instant::now();
@@ -1,15 +1,48 @@
 use nifty_udp::*;
 
 fn main() {
-    let mut server = Client::bind(
+    let mut server = Server::bind(
         ClientConfig {
             max_message_size: 65443,
 
             heartbeat_interval: 100,
             timeout: 10000,
+            handshake_timeout: 2000,
+            pause_timeout: None,
+            require_handshake_ack: false,
+            stall_fraction: None,
             ping_memory_length: 16,
+            min_ping_samples: None,
+            ping_smoothing: None,
+
+            ack_delay: None,
+            piggyback_acks: false,
 
             listen: true,
+            challenge_handshake: false,
+
+            strict_peers: false,
+            duplicate_instances: None,
+            expected_connections: 0,
+
+            max_packets_per_update: None,
+            fair_queuing: None,
+            low_water_mark: None,
+
+            report_invalid_packets: false,
+            sequenced_messages: false,
+            report_gaps: false,
+
+            receive_batch_size: 1,
+            recv_socket_buffer: None,
+
+            seed: None,
+
+            checksum: false,
+
+            reuse_address: false,
+            reuse_port: false,
+            bind_device: None,
 
             channels: vec![
                 ChannelConfig::ReceiveFecReliable,
@@ -26,6 +59,7 @@ fn main() {
                 Event::Message(addr, channel_id, message) => {
                     println!("message from {} on channel {} {:?}", addr, channel_id, std::str::from_utf8(&message).unwrap());
                 },
+                _ => (),
             }
         }
     }
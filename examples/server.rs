@@ -1,23 +1,65 @@
+use std::ops::ControlFlow;
+
 use nifty_udp::*;
 
 fn main() {
-    let mut server = Client::bind(
+    let mut server = Server::bind(
         ClientConfig {
             max_message_size: 65443,
 
             heartbeat_interval: 100,
             timeout: 10000,
+            handshake_timeout: 2000,
+            pause_timeout: None,
+            require_handshake_ack: false,
+            stall_fraction: None,
             ping_memory_length: 16,
+            min_ping_samples: None,
+            ping_smoothing: None,
+
+            ack_delay: None,
+            piggyback_acks: false,
 
             listen: true,
+            challenge_handshake: false,
+
+            strict_peers: false,
+            duplicate_instances: None,
+            expected_connections: 0,
+
+            max_packets_per_update: None,
+            fair_queuing: None,
+            low_water_mark: None,
+
+            report_invalid_packets: false,
+            sequenced_messages: false,
+            report_gaps: false,
+
+            receive_batch_size: 1,
+            recv_socket_buffer: None,
+
+            seed: None,
+
+            checksum: false,
+
+            reuse_address: false,
+            reuse_port: false,
+            bind_device: None,
 
             channels: vec![
                 ChannelConfig::ReceiveUnreliable,
-                ChannelConfig::SendUnreliable,
+                ChannelConfig::SendUnreliable {
+                    queue_limit: 1,
+                    rate_limit: None,
+                },
 
                 ChannelConfig::ReceiveReliable,
                 ChannelConfig::SendReliable {
-                    resend_threshhold: 1.25
+                    resend_threshhold: 1.25,
+                    adaptive_resend: None,
+                    max_retransmits: None,
+                    rate_limit: None,
+                    lifetime: None,
                 },
             ],
         },
@@ -25,16 +67,19 @@ fn main() {
     ).unwrap();
 
     loop {
-        for event in server.update().unwrap() {
+        server.for_each_event(|server, event| {
             match event {
                 Event::Connection(addr) => println!("connection {}", addr),
                 Event::Disconnection(addr, reason) => println!("disconnected {} {:?}", addr, reason),
                 Event::Message(addr, channel_id, message) => {
                     println!("message from {} on channel {} {:?}", addr, channel_id, std::str::from_utf8(&message).unwrap());
 
-                    server.send(addr, 3, "Pong".as_bytes()).unwrap();
+                    server.send_str(addr, 3, "Pong").unwrap();
                 },
+                _ => (),
             }
-        }
+
+            ControlFlow::Continue(())
+        }).unwrap();
     }
 }
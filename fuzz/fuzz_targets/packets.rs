@@ -0,0 +1,83 @@
+//! feeds arbitrary packets from a handful of addresses into an endpoint with every channel type
+//!
+//! the input is split into packets, each starting with a byte choosing the origin and a big endian u16 length
+
+#![no_main]
+
+use std::net::SocketAddr;
+
+use libfuzzer_sys::fuzz_target;
+use nifty_udp::*;
+
+fuzz_target!(|data: &[u8]| {
+    let mut endpoint = Endpoint::bind(
+        ClientConfig {
+            max_message_size: 1200,
+            heartbeat_interval: 100,
+            timeout: 1000,
+            handshake_timeout: 1000,
+            pause_timeout: None,
+            require_handshake_ack: false,
+            stall_fraction: None,
+            ping_memory_length: 4,
+            min_ping_samples: None,
+            ping_smoothing: None,
+
+            ack_delay: None,
+            piggyback_acks: false,
+
+            listen: true,
+            challenge_handshake: false,
+            strict_peers: false,
+            duplicate_instances: Some(DuplicateInstance::Migrate),
+            expected_connections: 0,
+
+            reuse_address: false,
+            reuse_port: false,
+            bind_device: None,
+
+            max_packets_per_update: None,
+            fair_queuing: None,
+            low_water_mark: None,
+            report_invalid_packets: true,
+            sequenced_messages: false,
+            report_gaps: true,
+            receive_batch_size: 1,
+            recv_socket_buffer: None,
+            seed: Some(0),
+
+            checksum: false,
+
+            channels: vec![
+                ChannelConfig::SendUnreliable { queue_limit: 1, rate_limit: None },
+                ChannelConfig::ReceiveUnreliable,
+                ChannelConfig::SendUnreliableBatched,
+                ChannelConfig::ReceiveUnreliableBatched,
+                ChannelConfig::SendReliable { resend_threshhold: 1.25, adaptive_resend: Some(AdaptiveResend { min: 1., max: 4. }), max_retransmits: Some(4), rate_limit: None, lifetime: None },
+                ChannelConfig::ReceiveReliable,
+                ChannelConfig::ReceiveReliableStream,
+                ChannelConfig::SendFecReliable { resend_threshhold: 1.25, adaptive_resend: None, max_retransmits: Some(4), rate_limit: None, max_data_symbols: 4, max_repair_symbols: 2 },
+                ChannelConfig::ReceiveFecReliable,
+                ChannelConfig::ReceiveFecReliableOrdered,
+                ChannelConfig::SendUnreliableSequenced,
+                ChannelConfig::ReceiveUnreliableSequenced { jitter_buffer: Some(40) },
+                ChannelConfig::ReceiveReliableRequests,
+            ],
+        },
+        "127.0.0.1:0".parse().unwrap(),
+    ).unwrap();
+
+    let mut data = data;
+
+    while let [origin, len_high, len_low, rest @ ..] = data {
+        let len = (u16::from_be_bytes([*len_high, *len_low]) as usize).min(rest.len());
+        let (packet, rest) = rest.split_at(len);
+
+        let origin = SocketAddr::from(([127, 0, 0, 1], 40000 + (origin % 4) as u16));
+        let _ = endpoint.fuzz_packet(packet, origin);
+
+        data = rest;
+    }
+
+    let _ = endpoint.update();
+});
@@ -0,0 +1,97 @@
+//! reading icmp port unreachable errors from the socket error queue on linux
+//!
+//! without `IP_RECVERR` linux doesn't report icmp errors on unconnected udp sockets at all,
+//! with it an error fails the next send or receive with `ConnectionRefused` and is queued with the address it was for
+
+use std::{net::{UdpSocket, SocketAddr}, os::fd::AsRawFd};
+
+use crate::mmsg::to_socket_addr;
+
+
+/// sets `IP_RECVERR` or `IPV6_RECVERR` depending on the address the socket is bound to
+pub(crate) fn enable(socket: &UdpSocket) -> std::io::Result<()> {
+    let (level, name) = match socket.local_addr()? {
+        SocketAddr::V4(_) => (libc::SOL_IP, libc::IP_RECVERR),
+        SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IPV6_RECVERR),
+    };
+
+    let enabled: libc::c_int = 1;
+
+    // safety: the option value is a c_int that outlives the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// empties the error queue, returning the addresses that were reported as having nothing bound to them
+///
+/// other errors, like the path mtu being exceeded, are discarded
+pub(crate) fn unreachable_addrs(socket: &UdpSocket) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+
+    loop {
+        // the payload of the packet that caused the error isn't needed
+        let mut payload = [0u8; 1];
+        let mut iovec = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        };
+
+        // safety: sockaddr_storage is plain data and valid when zeroed
+        let mut address: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        // u64s to align the control messages
+        let mut control = [0u64; 64];
+
+        // safety: msghdr is plain data and valid when zeroed
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        header.msg_name = &mut address as *mut libc::sockaddr_storage as *mut libc::c_void;
+        header.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        // safety: the header points to an address, buffer and control buffer that outlive the call
+        let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut header, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+
+        if received < 0 {
+            break;
+        }
+
+        // safety: the control messages were written by the kernel and are walked with the cmsg macros
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&header);
+
+            while !cmsg.is_null() {
+                let is_extended_error = ((*cmsg).cmsg_level == libc::SOL_IP && (*cmsg).cmsg_type == libc::IP_RECVERR)
+                    || ((*cmsg).cmsg_level == libc::SOL_IPV6 && (*cmsg).cmsg_type == libc::IPV6_RECVERR);
+
+                if is_extended_error {
+                    let error = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err);
+
+                    if error.ee_errno == libc::ECONNREFUSED as u32 {
+                        if let Some(addr) = to_socket_addr(&address) {
+                            addrs.push(addr);
+                        }
+                    }
+                }
+
+                cmsg = libc::CMSG_NXTHDR(&header, cmsg);
+            }
+        }
+    }
+
+    addrs
+}
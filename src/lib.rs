@@ -1,20 +1,90 @@
-use std::{net::{UdpSocket, SocketAddr}, time::{Instant, UNIX_EPOCH, SystemTime}, collections::{HashMap, hash_map::Entry, VecDeque}, io::Write};
+#[cfg(target_os = "linux")]
+mod errqueue;
+mod loopback;
+#[cfg(target_os = "linux")]
+mod mmsg;
+mod roles;
+#[cfg(feature = "serde")]
+mod serde_millis;
+mod wire;
+
+pub use roles::{Server, Client, ClientPool};
+
+use std::{net::{UdpSocket, SocketAddr}, time::{Instant, UNIX_EPOCH, SystemTime, Duration}, collections::{HashMap, HashSet, hash_map::Entry, VecDeque, BTreeMap}, ops::{ControlFlow, Range}, io::Read, hash::{BuildHasher, RandomState}};
+
+use wire::{ReliableHeader, Fragment, ReliableAck, PiggybackedAck, FecHeader, FecAck, StreamHeader, RequestHeader, BATCHED_PREFIX_LEN, SEQUENCED_PREFIX_LEN, CHECKSUM_LEN};
 
 
 /// describes the static behavior of a client
 ///
 ///
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientConfig {
-    /// max message size in bytes, maximum is 65443 allowed by udp over IPv4
+    /// max message size in bytes including headers
+    ///
+    /// maximum is 65507 allowed by udp over IPv4, or 65527 over IPv6, depending on the address bound to,
+    /// and minimum is 33 to fit a heartbeat, or 37 with `ClientConfig::checksum`
+    ///
+    /// see `Endpoint::max_payload` for the largest message each channel can send
     pub max_message_size: u16,
 
     /// interval to send heartbeats at to prevent timeout
+    ///
+    /// 0 never sends heartbeats after the first, for short request/response exchanges where the traffic itself keeps the connection alive,
+    /// anything received still resets the timeout and `Endpoint::ping_now` can still be used to check on the peer
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis"))]
     pub heartbeat_interval: u128,
     /// timeout length for when to close a connection for not responding
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis"))]
     pub timeout: u128,
+    /// timeout length for when to close a connection that has never echoed back a heartbeat
+    ///
+    /// usually shorter than `timeout` so that connecting to an address with nothing on it fails quickly
+    #[cfg_attr(feature = "serde", serde(with = "serde_millis"))]
+    pub handshake_timeout: u128,
+    /// how long a connection paused with `Endpoint::pause_connection` is kept before timing out
+    ///
+    /// `None` keeps paused connections until they're resumed, though the peer will still time out it's side after it's own `timeout`
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_millis::option"))]
+    pub pause_timeout: Option<u128>,
+    /// set to true to hold back the `Event::Connection` for `Endpoint::connect` until the peer has echoed back a heartbeat,
+    /// so it's only emitted once the peer is known to exist
+    ///
+    /// a connection that never gets an echo is still dropped with `DisconnectReason::HandshakeTimeout`,
+    /// giving an `Event::Disconnection` without an `Event::Connection` before it
+    pub require_handshake_ack: bool,
+    /// fraction of `timeout` a connection can go without receiving anything before an `Event::ConnectionStalled`,
+    /// followed by an `Event::ConnectionRecovered` if it starts receiving again before timing out
+    ///
+    /// `None` never reports stalls
+    pub stall_fraction: Option<f32>,
 
     /// how many ping time samples to keep
     pub ping_memory_length: u8,
+    /// how many ping samples a connection needs before it has a ping and gives an `Event::PingEstablished`,
+    /// so the first few samples can't give a noisy ping for resends
+    ///
+    /// `None` uses the first sample, more than `ping_memory_length` waits for a full memory
+    pub min_ping_samples: Option<u8>,
+    /// weight of each new sample in an exponentially weighted moving average of the ping, like `0.125` for the smoothed round trip time of tcp,
+    /// which reacts to changes smoothly instead of all at once when a sample leaves the memory
+    ///
+    /// `None` averages the samples in the memory evenly
+    pub ping_smoothing: Option<f32>,
+
+    /// how many milliseconds a receive reliable channel can hold acks for, so more of them are coalesced into each ack packet
+    ///
+    /// acks are sent by the first update or received packet after the oldest has waited this long,
+    /// so it's only exceeded by the time between calls to `Endpoint::update` when nothing is being received
+    ///
+    /// `None` sends acks on every update
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_millis::option"))]
+    pub ack_delay: Option<u128>,
+    /// set to true to send acks held back by `ack_delay` in the next message sent with `Endpoint::send` on a reliable channel to the same connection,
+    /// instead of in a packet of their own, halving the packets needed for request and response traffic
+    ///
+    /// the other client has to know the format, so both sides of a connection should set it the same
+    pub piggyback_acks: bool,
 
     /// set to true to accept incoming connections
     ///
@@ -22,920 +92,5080 @@ pub struct ClientConfig {
     ///
     /// generally true for servers and false for clients
     pub listen: bool,
+    /// set to true to make a new address answer a challenge before a listening endpoint accepts it,
+    /// proving it can receive at the address it claims so a spoofed address can't open connections
+    ///
+    /// a heartbeat from a new address gets a challenge smaller than itself and nothing else from a new address gets a reply,
+    /// so it can't be used for amplification either. connecting endpoints answer challenges on their own whether or not it's set,
+    /// though the connection takes an extra round trip. with `DuplicateInstance::Migrate` a peer has to answer one from it's new address
+    /// before the connection moves there
+    pub challenge_handshake: bool,
+
+    /// set to true to drop every packet from an address that isn't already connected
+    ///
+    /// unlike `listen: false` no disconnect packet is sent in reply, unknown addresses are ignored entirely
+    ///
+    /// connections can then only be made with `Endpoint::connect`
+    pub strict_peers: bool,
+    /// what to do when a heartbeat from a new address has the instance id of an existing connection from another address,
+    /// like a client whose nat gave it a new port
+    ///
+    /// `None` treats them as unrelated connections
+    pub duplicate_instances: Option<DuplicateInstance>,
+
+    /// how many connections to allocate space for up front, so a burst of connections doesn't keep reallocating
+    ///
+    /// more connections can still be made, 0 allocates as needed
+    pub expected_connections: usize,
+
+    /// set to true to bind with `SO_REUSEADDR`, allowing a restarted server to bind to the same address straight away
+    pub reuse_address: bool,
+    /// set to true to bind with `SO_REUSEPORT`, allowing multiple processes to bind to the same port, only used on unix
+    pub reuse_port: bool,
+    /// name of a network interface to bind to with `SO_BINDTODEVICE`, like `eth0`, so only traffic through it is sent and received
+    ///
+    /// only supported on linux, where it may need `CAP_NET_RAW`, other platforms return `Error::BindDeviceUnsupported`
+    /// from `Endpoint::bind` so bind to the interface's address instead
+    pub bind_device: Option<String>,
+
+    /// max number of received packets to process in a single call to `Endpoint::update`
+    ///
+    /// any remaining packets stay queued on the socket until the next update,
+    /// which stops a flood of packets from blocking heartbeats and timeouts
+    ///
+    /// `None` processes every packet available
+    pub max_packets_per_update: Option<usize>,
+
+    /// shares a send budget for each connection between it's channels by weight, so one busy channel can't starve the others
+    ///
+    /// each channel's own rate limit still applies on top, `None` leaves every channel limited only by it's own rate limit
+    pub fair_queuing: Option<FairQueuing>,
+
+    /// gives an `Event::ChannelWritable` when a reliable channel with at least this many messages waiting for acknowledgement
+    /// drops back under it, so a producer holding back data knows when to send more
+    ///
+    /// `None` never gives the event
+    pub low_water_mark: Option<usize>,
+
+    /// max number of packets to receive with a single system call, only used on linux
+    ///
+    /// each packet in a batch needs it's own buffer of `max_message_size`
+    ///
+    /// 1 receives a single packet at a time, which is what other platforms always do
+    pub receive_batch_size: usize,
+    /// size in bytes to set the os receive buffer (`SO_RCVBUF`) of the socket to, so bursts of packets aren't dropped before they're received
+    ///
+    /// `None` leaves the os default, see `Endpoint::set_recv_buffer_size` to change it after binding
+    pub recv_socket_buffer: Option<usize>,
+
+    /// seed for the random number generator used for anything random, like the client's instance id
+    ///
+    /// set for reproducible behavior in tests, clients with the same seed will have the same instance id
+    ///
+    /// `None` seeds from the system clock
+    pub seed: Option<u64>,
+
+    /// set to true to append a crc32 to every packet and drop received packets that don't match it,
+    /// counted in `Diagnostics::corrupt_packets`
+    ///
+    /// catches corruption that gets past udp's own checksum, which is optional over ipv4, before it reaches a reliable or fec message
+    ///
+    /// takes 4 bytes from `max_message_size`, and both peers need the same setting
+    pub checksum: bool,
+
+    /// set to true to get an `Event::InvalidPacket` for every packet that gets dropped for being malformed
+    ///
+    /// useful for debugging other implementations, but any address can cause these events
+    pub report_invalid_packets: bool,
+
+    /// set to true to get an `Event::SequencedMessage` with the sender's sequence number
+    /// instead of an `Event::Message` for messages on reliable, fec and unreliable sequenced channels
+    pub sequenced_messages: bool,
+    /// set to true to get an `Event::Gap` for the sequences skipped over on a receive reliable channel,
+    /// and an `Event::GapFilled` as each one arrives
+    pub report_gaps: bool,
 
     /// list of channel configurations
     ///
     /// each channel should correspond to it's opposite receive/send on any other client
+    ///
+    /// can be left empty to only keep connections alive and measure ping, see `Endpoint::is_reachable`
     pub channels: Vec<ChannelConfig>,
 }
 
+/// the behavior of a single channel, see `ClientConfig::channels`
+///
+/// with the `serde` feature the variant is given by a `type` field, like `{ "type": "SendUnreliable", "queue_limit": 1, "rate_limit": null }`
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
 pub enum ChannelConfig {
-    SendUnreliable,
+    SendUnreliable {
+        /// max number of messages to hold while sending is deferred
+        ///
+        /// when full the oldest message is dropped, so the newest data is what gets sent
+        ///
+        /// the newest message is always kept, so a limit of 0 behaves the same as 1
+        queue_limit: usize,
+        /// max bytes per second to send on the channel, `None` for no limit
+        ///
+        /// messages over the limit are held in the queue until the next update with budget for them
+        rate_limit: Option<u64>,
+    },
     ReceiveUnreliable,
 
+    /// sends every message prefixed by it's length so `Endpoint::send_batch` can pack many into one packet
+    SendUnreliableBatched,
+    /// receives each message in a packet from a `SendUnreliableBatched` channel as a separate `Event::Message`
+    ReceiveUnreliableBatched,
+
+    /// sends every message with a sequence number so the receiving channel can drop or reorder ones that arrive out of order
+    SendUnreliableSequenced,
+    /// drops messages older than one that has already been received, as the newer data replaces them
+    ReceiveUnreliableSequenced {
+        /// milliseconds to hold a message that arrived after a gap, waiting for the messages before it so they can be given in order
+        ///
+        /// messages that arrive after a later one has been given are too late and dropped,
+        /// `None` gives every message as soon as it arrives
+        #[cfg_attr(feature = "serde", serde(default, with = "serde_millis::option"))]
+        jitter_buffer: Option<u128>,
+    },
+
     SendReliable {
         /// at what multiple after the connections average ping time should a message be resent
+        ///
+        /// doubles each time a message is resent, up to 32 times the threshhold, to back off from an unresponsive peer
         resend_threshhold: f32,
+        /// bounds to adapt the resend threshhold between based on how acks arrive, `None` keeps it fixed
+        adaptive_resend: Option<AdaptiveResend>,
+        /// how many times a message can be resent before the connection is dropped with `DisconnectReason::ReliableFailure`
+        ///
+        /// `None` resends until the connection times out
+        max_retransmits: Option<u32>,
+        /// max bytes per second to send on the channel including resends, `None` for no limit
+        ///
+        /// sends over the limit are deferred until an update with budget for them
+        rate_limit: Option<u64>,
+        /// milliseconds after sending that a message stops being resent, for data that's useless once it's late, like voice
+        ///
+        /// once a message has expired every packet on the channel carries `8` more bytes so the receiver can stop waiting for it,
+        /// which comes out of the max payload. `None` resends until the message is acknowledged
+        #[cfg_attr(feature = "serde", serde(default, with = "serde_millis::option"))]
+        lifetime: Option<u128>,
     },
     ReceiveReliable,
+    /// receives the chunks of streams sent with `Endpoint::send_stream` on the opposite `SendReliable` channel,
+    /// giving an `Event::StreamProgress` for each chunk and an `Event::Stream` once the whole stream has arrived
+    ReceiveReliableStream,
+    /// receives requests sent with `Endpoint::request` and responses sent with `Endpoint::respond` on the opposite `SendReliable` channel,
+    /// giving an `Event::Request` or `Event::Response` for each
+    ReceiveReliableRequests,
 
     SendFecReliable {
         resend_threshhold: f32,
+        adaptive_resend: Option<AdaptiveResend>,
+        max_retransmits: Option<u32>,
+        rate_limit: Option<u64>,
+        /// max number of symbols a message is split into, 1 or at least 4 as `raptor_code` can't encode 2 or 3
         max_data_symbols: usize,
+        /// number of extra symbols sent with every message so it can be decoded with some symbols lost
+        ///
+        /// together with `max_data_symbols` it can't be more than 255, as the symbol index is sent as a single byte
         max_repair_symbols: usize,
     },
     ReceiveFecReliable,
+    /// holds decoded messages until every message sent before them has been received, so they arrive in order
+    ReceiveFecReliableOrdered,
 }
 
+/// a connection wide rate limit shared out between the `SendUnreliable`, `SendReliable` and `SendFecReliable` channels,
+/// see `ClientConfig::fair_queuing`
+///
+/// each channel gets a share of the budget in proportion to it's weight, what a channel with nothing to send doesn't use
+/// goes to the others, so a lone busy channel can use the whole budget
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FairQueuing {
+    /// max bytes per second to send on each connection, including resends
+    pub rate_limit: u64,
+    /// weight of each channel by channel id, channels without one and weights of 0 are treated as 1
+    pub weights: Vec<u32>,
+}
 
-const CHANNEL_OFFSET: u8 = 3;
+impl FairQueuing {
+    fn weight(&self, channel_id: u8) -> f64 {
+        self.weights.get(channel_id as usize).copied().unwrap_or(1).max(1) as f64
+    }
 
+    /// the most of the budget a channel can hold, a tenth of a second's worth like `Pacer`
+    fn burst(&self) -> f64 {
+        self.rate_limit as f64 / 10.
+    }
 
-pub(crate) struct Socket {
-    socket: UdpSocket,
+    /// the total weight of the channels sharing the budget, from their configs
+    fn total_weight(&self, channels: &[ChannelConfig]) -> f64 {
+        channels.iter().enumerate().filter(|(_, config)| config.is_paced()).map(|(channel_id, _)| self.weight(channel_id as u8)).sum()
+    }
+}
 
-    in_buffer: Vec<u8>,
-    out_buffer: Vec<u8>,
+/// see `ClientConfig::duplicate_instances`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DuplicateInstance {
+    /// moves the existing connection to the new address, keeping it's channels and context, with an `Event::ConnectionMigrated`
+    ///
+    /// a connection already made by earlier packets from the new address is disconnected with `DisconnectReason::Replaced` first
+    Migrate,
+    /// closes the new address, leaving the existing connection as it is
+    Reject,
+}
 
-    max_message_size: usize,
+/// bounds for adapting the resend threshhold of a reliable or fec channel
+///
+/// the threshhold is lowered a little each time a message is acknowledged without being resent,
+/// and raised by half each time an ack shows a resend wasn't needed
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptiveResend {
+    pub min: f32,
+    pub max: f32,
 }
 
-impl Socket {
-    fn new(max_message_size: u16, bind_addr: SocketAddr) -> Result<Self, Error> {
-        let socket = UdpSocket::bind(bind_addr)?;
+impl AdaptiveResend {
+    /// adjusts a resend threshhold when a message gets acknowledged
+    fn adapt(&self, resend_threshhold: &mut f32, retransmits: u32, last_sent: Option<Instant>, ping: Option<u128>) {
+        let (Some(ping), Some(last_sent)) = (ping, last_sent) else {return;};
 
-        let max_message_size = max_message_size as usize;
+        if retransmits == 0 {
+            *resend_threshhold -= 0.05;
+        } else if (last_sent.elapsed().as_millis() as f32) < ping as f32 / 2. {
+            // the ack arrived too soon after the resend to be for it, so the first send was only late
+            *resend_threshhold *= 1.5;
+        }
 
-        Ok(Socket {
-            socket,
+        *resend_threshhold = resend_threshhold.max(self.min).min(self.max);
+    }
+}
 
-            in_buffer: vec![0; max_message_size],
-            out_buffer: Vec::with_capacity(max_message_size),
+impl ChannelConfig {
+    /// if sends on the channel go through a `Pacer`
+    fn is_paced(&self) -> bool {
+        matches!(self, ChannelConfig::SendUnreliable { .. } | ChannelConfig::SendReliable { .. } | ChannelConfig::SendFecReliable { .. })
+    }
 
-            max_message_size,
-        })
+    /// if nothing is ever sent back to the channel, not even acks, so any packet arriving on it is from a peer that thinks it's a send channel too
+    fn is_send_only(&self) -> bool {
+        matches!(self, ChannelConfig::SendUnreliable { .. } | ChannelConfig::SendUnreliableBatched | ChannelConfig::SendUnreliableSequenced)
     }
 
-    fn clear_buffer(&mut self) {
-        self.out_buffer.clear();
+    fn rate_limit(&self) -> Option<u64> {
+        match self {
+            ChannelConfig::SendUnreliable { rate_limit, .. } => *rate_limit,
+            ChannelConfig::SendReliable { rate_limit, .. } => *rate_limit,
+            ChannelConfig::SendFecReliable { rate_limit, .. } => *rate_limit,
+            _ => None,
+        }
     }
 
-    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
-        if self.max_message_size - self.out_buffer.len() < bytes.len() {
-            Err(Error::MessageTooLong)
-        } else {
-            self.out_buffer.extend_from_slice(bytes);
+    /// the minimum length of a packet on the channel, not including the channel id
+    fn min_packet_len(&self) -> usize {
+        match self {
+            ChannelConfig::SendUnreliable { .. } => 0,
+            ChannelConfig::ReceiveUnreliable => 0,
 
-            Ok(())
-        }
+            ChannelConfig::SendUnreliableBatched => 0,
+            ChannelConfig::ReceiveUnreliableBatched => BATCHED_PREFIX_LEN,
 
-    }
+            ChannelConfig::SendUnreliableSequenced => 0,
+            ChannelConfig::ReceiveUnreliableSequenced { .. } => SEQUENCED_PREFIX_LEN,
 
-    fn send(&mut self, addr: SocketAddr) -> Result<usize, Error> {
-        Ok(self.socket.send_to(&self.out_buffer, addr)?)
+            ChannelConfig::SendReliable { .. } => ReliableAck::MIN_LEN,
+            ChannelConfig::ReceiveReliable => ReliableHeader::MIN_LEN,
+            ChannelConfig::ReceiveReliableStream => ReliableHeader::MIN_LEN,
+            ChannelConfig::ReceiveReliableRequests => ReliableHeader::MIN_LEN,
+
+            ChannelConfig::SendFecReliable { .. } => FecAck::MIN_LEN,
+            ChannelConfig::ReceiveFecReliable => FecHeader::LEN,
+            ChannelConfig::ReceiveFecReliableOrdered => FecHeader::LEN,
+        }
     }
 
-    fn receive(&mut self) -> Result<Option<(&[u8], SocketAddr)>, Error> {
-        loop {
-            match {
-                self.socket.set_nonblocking(true)?;
-                let result = self.socket.recv_from(&mut self.in_buffer);
-                self.socket.set_nonblocking(false)?;
-                result
-             } {
-                Err(err) => {
-                    match err.kind() {
-                        std::io::ErrorKind::WouldBlock => break Ok(None),
-                        std::io::ErrorKind::ConnectionReset => continue,
-                        _ => break Err(err.into()),
-                    }
-                },
-                Ok((received_bytes, origin)) => {
-                    break Ok(Some((&self.in_buffer[..received_bytes], origin)))
-                }
+    /// checks the fec parameters, returning `Error::InvalidFecConfig` for ones that can't be encoded
+    fn validate(&self) -> Result<(), Error> {
+        if let ChannelConfig::SendFecReliable { max_data_symbols, max_repair_symbols, .. } = self {
+            if *max_data_symbols == 0 || (2..=3).contains(max_data_symbols) || max_data_symbols.saturating_add(*max_repair_symbols) > u8::MAX as usize {
+                return Err(Error::InvalidFecConfig);
             }
         }
-    }
 
-    fn heartbeat(&mut self, addr: SocketAddr, instance: &[u8; 16], time: u128) -> Result<(), Error> {
-        self.clear_buffer();
-        self.write(&[0])?;
-        self.write(instance)?;
-        self.write(&time.to_be_bytes())?;
-        self.send(addr)?;
         Ok(())
     }
 
-    fn close(&mut self, addr: SocketAddr) -> Result<(), Error> {
-        self.clear_buffer();
-        self.write(&[1])?;
-        self.send(addr)?;
-        Ok(())
+    /// the largest message that can be sent on the channel, `None` for receive channels
+    fn max_payload(&self, max_message_size: usize) -> Option<usize> {
+        // every packet starts with the channel id
+        let max_packet_len = max_message_size.saturating_sub(1);
+
+        match self {
+            ChannelConfig::SendUnreliable { .. } => Some(max_packet_len),
+            ChannelConfig::SendUnreliableBatched => Some(max_packet_len.saturating_sub(BATCHED_PREFIX_LEN).min(u16::MAX as usize)),
+            ChannelConfig::SendUnreliableSequenced => Some(max_packet_len.saturating_sub(SEQUENCED_PREFIX_LEN)),
+            ChannelConfig::SendReliable { lifetime, .. } => {
+                // room is kept for a skip once messages start expiring
+                let max_packet_len = max_packet_len.saturating_sub(if lifetime.is_some() {ReliableHeader::SKIP_LEN} else {0});
+
+                // longer messages are split into up to `u16::MAX` fragments
+                let max_fragment_len = max_packet_len.saturating_sub(ReliableHeader::MAX_LEN);
+                Some(max_packet_len.saturating_sub(ReliableHeader::MIN_LEN).max(max_fragment_len.saturating_mul(u16::MAX as usize)))
+            },
+            ChannelConfig::SendFecReliable { max_data_symbols, .. } => {
+                // messages are split into up to `max_data_symbols` symbols, each with a header,
+                // and the message length is sent as a u16
+                let max_symbol_len = max_packet_len.saturating_sub(FecHeader::LEN);
+                Some(max_symbol_len.saturating_mul(*max_data_symbols).min(u16::MAX as usize))
+            },
+            _ => None,
+        }
     }
+}
 
-    fn channel_prefix(&mut self, channel_id: u8) -> Result<(), Error> {
-        self.clear_buffer();
-        self.write(&[channel_id + CHANNEL_OFFSET])?;
-        Ok(())
+/// updates the endpoint of `target` and passes each event to a handler along with `target`,
+/// used for `for_each_event` on endpoints, servers and clients
+///
+/// events the handler didn't get because it broke are queued again ahead of any it caused
+fn handle_events<T, C: Default>(target: &mut T, endpoint: fn(&mut T) -> &mut Endpoint<C>, mut f: impl FnMut(&mut T, Event) -> ControlFlow<()>) -> Result<(), Error> {
+    let mut events = endpoint(target).update()?.into_iter();
+
+    for event in events.by_ref() {
+        if f(target, event).is_break() {break;}
     }
+
+    let endpoint = endpoint(target);
+    let queued = std::mem::take(&mut endpoint.events);
+    endpoint.events = events.chain(queued).collect();
+
+    Ok(())
 }
 
 
-pub struct Client {
-    socket: Socket,
+/// largest payload of a udp packet sent from an address
+///
+/// the 65535 byte limit of an IPv4 packet includes it's 20 byte header, where IPv6 only limits the payload,
+/// both then have the 8 byte udp header
+fn max_udp_payload(addr: SocketAddr) -> usize {
+    match addr {
+        SocketAddr::V4(_) => 65535 - 20 - 8,
+        SocketAddr::V6(_) => 65535 - 8,
+    }
+}
 
-    instance: [u8; 16],
 
-    connections: HashMap<SocketAddr, Connection>,
+const CHANNEL_OFFSET: u8 = 3;
+/// the first byte of a packet from `Endpoint::send_raw`, past the last channel id so it can't collide with one
+const RAW_PREFIX: u8 = u8::MAX;
+/// the first byte of a packet from `Endpoint::send_broadcast` or `Endpoint::send_discovery`, also past the last channel id
+const DISCOVERY_PREFIX: u8 = u8::MAX - 1;
+/// the first byte of a challenge or it's answer, see `ClientConfig::challenge_handshake`
+const CHALLENGE_PREFIX: u8 = u8::MAX - 2;
+
+/// how many milliseconds a challenge can be answered in, it's accepted until the end of the next interval
+const CHALLENGE_INTERVAL: u128 = 10000;
+
+/// how far past the oldest message not yet received a reliable or fec channel accepts messages,
+/// so a peer can't make it buffer an unbounded number of sequences
+const RECEIVE_WINDOW: u64 = 1 << 16;
+
+/// `raptor_code` panics encoding messages shorter than this, so they're padded up to it
+const MIN_FEC_SOURCE_LEN: usize = 3;
+
+/// the resend threshhold of a message doubles each time it's resent, up to this many times
+const MAX_RESEND_BACKOFF: u32 = 5;
+
+/// the most packets held to send again after the socket's send buffer was full, older ones are dropped past this
+const MAX_SEND_RETRIES: usize = 1024;
+
+/// true if a send failed because the socket's send buffer is full, which clears up on it's own
+fn is_send_buffer_full(err: &std::io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if err.raw_os_error() == Some(libc::ENOBUFS) {
+        return true;
+    }
 
-    config: ClientConfig,
+    err.kind() == std::io::ErrorKind::WouldBlock
+}
 
-    events: Vec<Event>,
+/// how long each fragment of a message on a reliable channel is, `None` if it fits in a single packet
+///
+/// the skip length is kept free in every packet of a channel with a lifetime, see `ChannelConfig::SendReliable`
+fn reliable_fragment_len(message_len: usize, max_message_size: usize, skip_len: usize) -> Option<usize> {
+    if message_len <= max_message_size.saturating_sub(1 + ReliableHeader::MIN_LEN + skip_len) {
+        None
+    } else {
+        Some(max_message_size.saturating_sub(1 + ReliableHeader::MAX_LEN + skip_len).max(1))
+    }
 }
 
-impl Client {
-    pub fn bind(config: ClientConfig, bind_addr: SocketAddr) -> Result<Self, Error> {
-        if config.channels.len() > (u8::MAX - CHANNEL_OFFSET) as usize {
-            return Err(Error::TooManyChannels);
-        }
+/// how much longer than the resend threshhold to wait before resending a message that has already been resent
+fn resend_backoff(retransmits: u32) -> f32 {
+    (1u32 << retransmits.min(MAX_RESEND_BACKOFF)) as f32
+}
 
-        let socket = Socket::new(config.max_message_size, bind_addr)?;
 
-        Ok(Client {
-            socket,
+/// what packets are sent and received through
+enum Transport {
+    Udp(UdpSocket),
+    /// an in process pair made by `Endpoint::loopback_pair`
+    Loopback(loopback::LoopbackPort),
+}
 
-            instance: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis().to_be_bytes(),
+pub(crate) struct Socket {
+    transport: Transport,
 
-            connections: HashMap::new(),
+    in_buffer: Vec<u8>,
+    out_buffer: Vec<u8>,
 
-            config,
+    /// packets waiting to be sent together by `Socket::flush_datagrams`
+    datagram_queue: Vec<(Vec<u8>, SocketAddr)>,
+    /// packets that couldn't be sent because the send buffer was full, sent again by `Socket::retry_sends` on the next update
+    send_retries: VecDeque<(Vec<u8>, SocketAddr)>,
 
-            events: Vec::new(),
-        })
-    }
+    /// used instead of `in_buffer` when receiving more than one packet at a time
+    #[cfg(target_os = "linux")]
+    receive_batch: Option<mmsg::ReceiveBatch>,
 
-    pub fn bind_any(config: ClientConfig) -> Result<Self, Error> {
-        Client::bind(config, "0.0.0.0:0".parse().unwrap())
-    }
+    /// sent after the time in every heartbeat, see `Endpoint::set_heartbeat_payload`
+    heartbeat_payload: Vec<u8>,
 
-    pub fn connect(&mut self, addr: SocketAddr) -> Result<(), Error> {
-        self.connections.insert(addr, Connection::new(&self.config, addr, &self.instance, &mut self.socket)?);
+    /// appends a checksum to every packet sent, see `ClientConfig::checksum`
+    checksum: bool,
 
-        self.events.push(Event::Connection(addr));
+    /// addresses that sends were rejected from with an icmp port unreachable since the last update, only found on linux
+    unreachable: Vec<SocketAddr>,
 
-        Ok(())
-    }
+    /// the most that can be written to a packet, not including the checksum
+    max_message_size: usize,
 
-    pub fn disconnect(&mut self, addr: SocketAddr) -> Result<bool, Error> {
-        Ok(if self.connections.remove(&addr).is_some() {
-            self.socket.close(addr)?;
-            self.events.push(Event::Disconnection(addr, DisconnectReason::Kicked));
+    /// everything that's gone through the socket, see `Endpoint::metrics_snapshot`
+    traffic: Traffic,
 
-            true
-        } else {
-            false
-        })
-    }
+    /// packets from `Endpoint::inject_raw`, received before anything from the transport
+    #[cfg(any(test, feature = "testing"))]
+    injected: VecDeque<(Vec<u8>, SocketAddr)>,
+}
 
-    pub fn disconnect_all(&mut self) -> Result<(), Error> {
-        for (addr, _) in std::mem::replace(&mut self.connections, HashMap::new()) {
-            self.socket.close(addr)?;
-            self.events.push(Event::Disconnection(addr, DisconnectReason::Kicked));
-        }
+/// packets and bytes sent and received by a socket, bytes include the checksum
+#[derive(Default)]
+struct Traffic {
+    packets_sent: u64,
+    bytes_sent: u64,
+    packets_received: u64,
+    bytes_received: u64,
+}
 
-        Ok(())
+impl Traffic {
+    fn sent(&mut self, len: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += len as u64;
     }
 
-    pub fn update(&mut self) -> Result<Vec<Event>, Error> {
-
-        // receive messages
-        while let Some((message, origin)) = self.socket.receive()? {
+    fn received(&mut self, len: usize) {
+        self.packets_received += 1;
+        self.bytes_received += len as u64;
+    }
+}
 
-            let mut channel_message = None;
-            let mut heartbeat_data: Option<([u8; 16], [u8; 16])> = None;
-            let mut time_response = None;
+impl Socket {
+    fn new(config: &ClientConfig, transport: Transport) -> Self {
+        let packet_len = config.max_message_size as usize;
+        let max_message_size = if config.checksum {packet_len.saturating_sub(CHECKSUM_LEN)} else {packet_len};
 
-            let valid_message = match message.get(0) {
-                None => false,
-                Some(0) => {
+        Socket {
+            transport,
 
-                    if let (Some(instance_bytes), Some(time_bytes)) = (message.get(1..17), message.get(17..33)) {
-                        heartbeat_data = Some((instance_bytes.try_into().unwrap(), time_bytes.try_into().unwrap()));
-                        true
-                    } else {
-                        false
-                    }
-                },
-                Some(1) => {
-                    if self.connections.remove(&origin).is_some() {
-                        self.events.push(Event::Disconnection(origin, DisconnectReason::Other));
-                    }
+            in_buffer: vec![0; packet_len],
+            out_buffer: Vec::with_capacity(packet_len),
 
-                    false
-                },
-                Some(2) => {
+            datagram_queue: Vec::new(),
+            send_retries: VecDeque::new(),
+            #[cfg(any(test, feature = "testing"))]
+            injected: VecDeque::new(),
 
-                    if let Some(bytes) = message.get(1..17) {
-                        time_response = Some(u128::from_be_bytes(bytes.try_into().unwrap()));
-                        true
-                    } else {
-                        false
-                    }
-                },
-                Some(channel_id) => {
-                    let channel_id = *channel_id - CHANNEL_OFFSET;
-                    if ((channel_id) as usize) < self.config.channels.len() {
-                        channel_message = Some((channel_id, Vec::from(&message[1..])));
-                        true
-                    } else {
-                        false
-                    }
-                },
-            };
+            #[cfg(target_os = "linux")]
+            receive_batch: (config.receive_batch_size > 1).then(|| mmsg::ReceiveBatch::new(config.receive_batch_size, packet_len)),
 
-            if valid_message {
-                let connection = match self.connections.entry(origin) {
-                    Entry::Occupied(entry) => entry.into_mut(),
-                    Entry::Vacant(entry) => {
-                        if self.config.listen {
-                            let connection = entry.insert(Connection::new(&self.config, origin, &self.instance, &mut self.socket)?);
-                            self.events.push(Event::Connection(origin));
-                            connection
-                        } else {
-                            self.socket.close(origin)?;
-                            continue;
-                        }
-                    },
-                };
+            heartbeat_payload: Vec::new(),
 
-                connection.last_received_keep_alive = Instant::now();
+            checksum: config.checksum,
 
-                if let Some((channel_id, message)) = channel_message {
-                    if let Some(channel) = connection.channels.get_mut(channel_id as usize) {
-                        for message in channel.receive(message, &mut self.socket)? {
-                            self.events.push(Event::Message(origin, channel_id, message));
-                        }
-                    }
-                }
+            unreachable: Vec::new(),
 
-                if let Some(time) = time_response {
-                    let diff = connection.creation_time.elapsed().as_millis() - time;
+            max_message_size,
 
-                    if connection.ping_memory.len() >= self.config.ping_memory_length as usize {
-                        connection.ping_memory.pop_front();
-                    }
-                    connection.ping_memory.push_back(diff);
+            traffic: Traffic::default(),
+        }
+    }
 
-                    connection.average_ping = Some(connection.ping_memory.iter().fold(0, |p, &e| p + e) / connection.ping_memory.len() as u128);
-                }
+    fn clear_buffer(&mut self) {
+        self.out_buffer.clear();
+    }
 
-                if let Some((instance, time)) = heartbeat_data {
-                    match connection.other_instance {
-                        None => connection.other_instance = Some(instance),
-                        Some(other_instance) => if instance != other_instance {
-                            self.connections.remove(&origin);
-                            self.events.push(Event::Disconnection(origin, DisconnectReason::OriginChangedInstance));
-                        }
-                    }
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.max_message_size.saturating_sub(self.out_buffer.len()) < bytes.len() {
+            Err(Error::MessageTooLong)
+        } else {
+            self.out_buffer.extend_from_slice(bytes);
 
-                    self.socket.clear_buffer();
-                    self.socket.write(&[2])?;
-                    self.socket.write(&time)?;
-                    self.socket.send(origin)?;
-                }
-            }
+            Ok(())
         }
 
-        // timeout clients
-        let mut to_remove = Vec::new();
+    }
 
-        for (&origin, connection) in self.connections.iter_mut() {
-            if connection.last_received_keep_alive.elapsed().as_millis() > self.config.timeout {
-                to_remove.push(origin);
-            }
+    fn send(&mut self, addr: SocketAddr) -> Result<usize, Error> {
+        let len = self.out_buffer.len();
+
+        if self.checksum {
+            let checksum = wire::checksum(&self.out_buffer);
+            self.out_buffer.extend_from_slice(&checksum);
         }
 
-        for addr in to_remove {
-            self.connections.remove(&addr);
+        let result = self.send_buffer(addr);
 
-            self.events.push(Event::Disconnection(addr, DisconnectReason::Timeout));
-        }
+        // the buffer is left as it was written in case it's sent again
+        self.out_buffer.truncate(len);
 
+        result
+    }
 
-        // update clients
-        for connection in self.connections.values_mut() {
-            connection.update(&self.instance, &mut self.socket)?;
+    fn send_buffer(&mut self, addr: SocketAddr) -> Result<usize, Error> {
+        match &self.transport {
+            Transport::Udp(socket) => {
+                let mut result = socket.send_to(&self.out_buffer, addr);
+
+                // an icmp error for an earlier packet fails the send, so it's tried again once the error has been read
+                #[cfg(target_os = "linux")]
+                if result.as_ref().is_err_and(|err| err.kind() == std::io::ErrorKind::ConnectionRefused) {
+                    self.unreachable.extend(errqueue::unreachable_addrs(socket));
+                    result = socket.send_to(&self.out_buffer, addr);
+                }
+
+                match result {
+                    Err(err) if is_send_buffer_full(&err) => {
+                        let datagram = self.out_buffer.clone();
+                        self.queue_retry(datagram, addr);
+                        Ok(self.out_buffer.len())
+                    },
+                    result => {
+                        let sent = result?;
+                        self.traffic.sent(sent);
+                        Ok(sent)
+                    },
+                }
+            },
+            Transport::Loopback(port) => {
+                let sent = port.send_to(&self.out_buffer, addr);
+                self.traffic.sent(sent);
+                Ok(sent)
+            },
         }
+    }
 
+    /// holds a packet to send again on the next update, see `Socket::send_retries`
+    fn queue_retry(&mut self, datagram: Vec<u8>, addr: SocketAddr) {
+        if self.send_retries.len() >= MAX_SEND_RETRIES {
+            self.send_retries.pop_front();
+        }
 
-        Ok(std::mem::replace(&mut self.events, Vec::new()))
+        self.send_retries.push_back((datagram, addr));
     }
 
-    pub fn send(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<(), Error> {
-        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+    /// sends packets held back by a full send buffer, oldest first, stopping if it's still full
+    fn retry_sends(&mut self) -> Result<(), Error> {
+        let Transport::Udp(socket) = &self.transport else {return Ok(());};
 
-        let Some(channel) = connection.channels.get_mut(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+        while let Some((datagram, addr)) = self.send_retries.front() {
+            match socket.send_to(datagram, *addr) {
+                Err(err) if is_send_buffer_full(&err) => break,
+                // icmp errors are picked up by the next receive
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => (),
+                result => self.traffic.sent(result?),
+            }
 
-        channel.send(message, &mut self.socket)?;
+            self.send_retries.pop_front();
+        }
 
         Ok(())
     }
 
-    pub fn send_single(&mut self, channel_id: u8, message: &[u8]) -> Result<(), Error> {
-        let mut addresses = self.connections.keys();
-        match (addresses.next(), addresses.next()) {
-            (None, None) => Err(Error::SendSingleInvalid),
-            (Some(_), Some(_)) => Err(Error::SendSingleInvalid),
-            (Some(&addr), None) => self.send(addr, channel_id, message),
-            _ => unreachable!(),
+    /// the udp socket packets are sent through, `Error::NoSocket` for loopback endpoints
+    fn udp(&self) -> Result<&UdpSocket, Error> {
+        match &self.transport {
+            Transport::Udp(socket) => Ok(socket),
+            Transport::Loopback(_) => Err(Error::NoSocket),
         }
     }
 
-    pub fn get_ping(&self, connection: SocketAddr) -> Result<Option<u128>, Error> {
-        self.connections.get(&connection).ok_or(Error::AddressNotConnected).and_then(|connection| Ok(connection.average_ping))
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        match &self.transport {
+            Transport::Udp(socket) => Ok(socket.local_addr()?),
+            Transport::Loopback(port) => Ok(port.local_addr()),
+        }
     }
 
-    pub fn connections(&self) -> impl Iterator<Item = SocketAddr> + '_ {
-        self.connections.keys().cloned()
-    }
+    /// queues the contents of the out buffer to be sent with `Socket::flush_datagrams`
+    fn queue_datagram(&mut self, addr: SocketAddr) {
+        let mut datagram = self.out_buffer.clone();
 
-    pub fn bound_addr(&self) -> Result<SocketAddr, Error> {
-        Ok(self.socket.socket.local_addr()?)
+        if self.checksum {
+            datagram.extend_from_slice(&wire::checksum(&datagram));
+        }
+
+        self.datagram_queue.push((datagram, addr));
     }
-}
 
-pub struct Connection {
-    addr: SocketAddr,
+    /// sends every queued packet, using as few system calls as possible on linux
+    ///
+    /// queued packets are dropped if sending fails, unless it's because the send buffer is full, then they're sent again on the next update
+    fn flush_datagrams(&mut self) -> Result<(), Error> {
+        let datagrams = std::mem::take(&mut self.datagram_queue);
+
+        let socket = match &self.transport {
+            Transport::Udp(socket) => socket,
+            Transport::Loopback(port) => {
+                for (datagram, addr) in datagrams.iter() {
+                    self.traffic.sent(port.send_to(datagram, *addr));
+                }
 
-    other_instance: Option<[u8; 16]>,
+                return Ok(());
+            },
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            let mut sent = 0;
+            let mut retried = false;
+            while sent < datagrams.len() {
+                match mmsg::send_batch(socket, &datagrams[sent..]) {
+                    Ok(batch_sent) => {
+                        for (datagram, _) in &datagrams[sent..sent + batch_sent] {
+                            self.traffic.sent(datagram.len());
+                        }
+
+                        sent += batch_sent;
+                    },
+                    // an icmp error for an earlier packet fails the send, so it's tried again once the error has been read
+                    Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused && !retried => {
+                        self.unreachable.extend(errqueue::unreachable_addrs(socket));
+                        retried = true;
+                    },
+                    Err(err) if is_send_buffer_full(&err) => {
+                        for (datagram, addr) in datagrams.into_iter().skip(sent) {
+                            self.queue_retry(datagram, addr);
+                        }
+
+                        return Ok(());
+                    },
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        for (i, (datagram, addr)) in datagrams.iter().enumerate() {
+            match socket.send_to(datagram, *addr) {
+                Err(err) if is_send_buffer_full(&err) => {
+                    for (datagram, addr) in datagrams.into_iter().skip(i) {
+                        self.queue_retry(datagram, addr);
+                    }
+
+                    return Ok(());
+                },
+                result => self.traffic.sent(result?),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Option<(&[u8], SocketAddr)>, Error> {
+        // cut short like a real packet too big for the buffer would be
+        #[cfg(any(test, feature = "testing"))]
+        if let Some((packet, origin)) = self.injected.pop_front() {
+            let received_bytes = packet.len().min(self.in_buffer.len());
+            self.in_buffer[..received_bytes].copy_from_slice(&packet[..received_bytes]);
+            self.traffic.received(received_bytes);
+            return Ok(Some((&self.in_buffer[..received_bytes], origin)));
+        }
+
+        let socket = match &mut self.transport {
+            Transport::Udp(socket) => socket,
+            Transport::Loopback(port) => {
+                let received = port.receive();
+
+                if let Some((packet, _)) = &received {
+                    self.traffic.received(packet.len());
+                }
+
+                return Ok(received);
+            },
+        };
+
+        #[cfg(target_os = "linux")]
+        if let Some(receive_batch) = &mut self.receive_batch {
+            let received = Socket::receive_batched(socket, receive_batch, &mut self.unreachable)?;
+
+            if let Some((packet, _)) = &received {
+                self.traffic.received(packet.len());
+            }
+
+            return Ok(received);
+        }
+
+        loop {
+            socket.set_nonblocking(true)?;
+            let result = socket.recv_from(&mut self.in_buffer);
+            socket.set_nonblocking(false)?;
+
+            match result {
+                Err(err) => {
+                    match err.kind() {
+                        std::io::ErrorKind::WouldBlock => break Ok(None),
+                        // windows reports icmp errors as a reset, but without the address it was for
+                        std::io::ErrorKind::ConnectionReset => continue,
+                        std::io::ErrorKind::ConnectionRefused => {
+                            #[cfg(target_os = "linux")]
+                            self.unreachable.extend(errqueue::unreachable_addrs(socket));
+                            continue;
+                        },
+                        _ => break Err(err.into()),
+                    }
+                },
+                Ok((received_bytes, origin)) => {
+                    self.traffic.received(received_bytes);
+                    break Ok(Some((&self.in_buffer[..received_bytes], origin)))
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn receive_batched<'a>(socket: &UdpSocket, receive_batch: &'a mut mmsg::ReceiveBatch, unreachable: &mut Vec<SocketAddr>) -> Result<Option<(&'a [u8], SocketAddr)>, Error> {
+        // only receive a new batch once the last one has been used up
+        if receive_batch.is_empty() {
+            while let Err(err) = receive_batch.fill(socket) {
+                match err.kind() {
+                    std::io::ErrorKind::WouldBlock => return Ok(None),
+                    std::io::ErrorKind::ConnectionReset => continue,
+                    std::io::ErrorKind::ConnectionRefused => unreachable.extend(errqueue::unreachable_addrs(socket)),
+                    _ => return Err(err.into()),
+                }
+            }
+        }
+
+        Ok(receive_batch.pop())
+    }
+
+    /// the heartbeat type, instance id and time written by `Socket::heartbeat` before any payload, the largest fixed packet there is
+    const HEARTBEAT_HEADER_LEN: usize = 1 + 16 + 16;
+
+    fn heartbeat(&mut self, addr: SocketAddr, instance: &[u8; 16], time: u128) -> Result<(), Error> {
+        self.clear_buffer();
+        self.write(&[0])?;
+        self.write(instance)?;
+        self.write(&time.to_be_bytes())?;
+        self.out_buffer.extend_from_slice(&self.heartbeat_payload);
+        self.send(addr)?;
+        Ok(())
+    }
+
+    fn close(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        self.clear_buffer();
+        self.write(&[1])?;
+        self.send(addr)?;
+        Ok(())
+    }
+
+    /// a challenge from a listening endpoint, or it's answer from a connecting one
+    fn challenge(&mut self, addr: SocketAddr, answer: bool, token: u64) -> Result<(), Error> {
+        self.clear_buffer();
+        self.write(&[CHALLENGE_PREFIX, answer as u8])?;
+        self.write(&token.to_be_bytes())?;
+        self.send(addr)?;
+        Ok(())
+    }
+
+    fn raw(&mut self, addr: SocketAddr, bytes: &[u8]) -> Result<(), Error> {
+        self.clear_buffer();
+        self.write(&[RAW_PREFIX])?;
+        self.write(bytes)?;
+        self.send(addr)?;
+        Ok(())
+    }
+
+    fn discovery(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<(), Error> {
+        self.clear_buffer();
+        self.write(&[DISCOVERY_PREFIX])?;
+        self.write(payload)?;
+        self.send(addr)?;
+        Ok(())
+    }
+
+    fn channel_prefix(&mut self, channel_id: u8) -> Result<(), Error> {
+        self.clear_buffer();
+        self.write(&[channel_id + CHANNEL_OFFSET])?;
+        Ok(())
+    }
+}
+
+
+/// the connection engine shared by `Server` and `Client`
+///
+/// can be used directly for endpoints that both accept and make connections, like peer to peer
+///
+/// each connection holds a context of type `C` for user data, see `Endpoint::with_context`
+pub struct Endpoint<C = ()> {
+    socket: Socket,
+
+    instance: [u8; 16],
+
+    /// keys the siphash of an address and interval that makes a challenge's token, see `ClientConfig::challenge_handshake`
+    ///
+    /// keyed from the os's randomness, it can't come from `Rng` since anyone who sees the instance id
+    /// in a heartbeat could work out the rest of it's output, and every endpoint would share it with `ClientConfig::seed` set
+    challenge_key: RandomState,
+    /// when the first challenge interval started
+    challenge_epoch: Instant,
+
+    connections: HashMap<SocketAddr, Connection<C>>,
+    /// addresses disconnected since the start of the last update, used to give a clearer error when sending to them
+    recently_disconnected: HashSet<SocketAddr>,
+
+    config: ClientConfig,
+
+    /// decides whether to accept a connection from a new address when listening
+    accept_filter: Option<AcceptFilter>,
+
+    /// counters from the endpoint and from connections that have since been removed
+    diagnostics: Diagnostics,
+    /// counters already returned by `Endpoint::take_diagnostics`, so `Endpoint::metrics_snapshot` never goes backwards
+    taken_diagnostics: Diagnostics,
+    /// connections announced with `Event::Connection` since the endpoint was created
+    connections_total: u64,
+
+    /// the id the next `Endpoint::request` gets
+    request_counter: u64,
+
+    events: Vec<Event>,
+}
+
+type AcceptFilter = Box<dyn FnMut(SocketAddr) -> bool + Send>;
+
+impl Endpoint {
+    pub fn bind(config: ClientConfig, bind_addr: SocketAddr) -> Result<Self, Error> {
+        let socket = socket2::Socket::new(socket2::Domain::for_address(bind_addr), socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+
+        // reuse options have to be set before binding
+        socket.set_reuse_address(config.reuse_address)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(config.reuse_port)?;
+
+        #[cfg(target_os = "linux")]
+        if let Some(bind_device) = &config.bind_device {
+            socket.bind_device(Some(bind_device.as_bytes()))?;
+        }
+
+        // binding to every interface instead would quietly send through ones the config meant to keep out
+        #[cfg(not(target_os = "linux"))]
+        if config.bind_device.is_some() {
+            return Err(Error::BindDeviceUnsupported);
+        }
+
+        socket.bind(&bind_addr.into())?;
+
+        Endpoint::from_socket(config, socket.into())
+    }
+
+    pub fn bind_any(config: ClientConfig) -> Result<Self, Error> {
+        Endpoint::bind(config, "0.0.0.0:0".parse().unwrap())
+    }
+
+    /// creates an endpoint from an already bound socket
+    ///
+    /// useful for sockets that have been configured before binding or inherited from another process
+    ///
+    /// `ClientConfig::reuse_address`, `ClientConfig::reuse_port` and `ClientConfig::bind_device` are ignored as the socket is already bound
+    ///
+    /// the socket must already be bound and shouldn't be connected to a single address,
+    /// the endpoint will switch it between blocking and non blocking as it needs
+    pub fn from_socket(config: ClientConfig, socket: UdpSocket) -> Result<Self, Error> {
+        if config.max_message_size as usize > max_udp_payload(socket.local_addr()?) {
+            return Err(Error::MaxMessageSizeTooLarge);
+        }
+
+        #[cfg(target_os = "linux")]
+        errqueue::enable(&socket)?;
+
+        if let Some(recv_socket_buffer) = config.recv_socket_buffer {
+            socket2::SockRef::from(&socket).set_recv_buffer_size(recv_socket_buffer)?;
+        }
+
+        Endpoint::from_transport(config, Transport::Udp(socket))
+    }
+
+    /// creates a pair of endpoints connected to each other in process, without any sockets or os networking
+    ///
+    /// the endpoints are at `127.0.0.1:1` and `127.0.0.1:2` respectively as far as each other are concerned,
+    /// packets sent to any other address are dropped
+    ///
+    /// useful for testing, the pair has no latency or packet loss, anything to do with the socket returns `Error::NoSocket`
+    pub fn loopback_pair(config_a: ClientConfig, config_b: ClientConfig) -> Result<(Self, Self), Error> {
+        let (port_a, port_b) = loopback::pair();
+
+        Ok((
+            Endpoint::from_transport(config_a, Transport::Loopback(port_a))?,
+            Endpoint::from_transport(config_b, Transport::Loopback(port_b))?,
+        ))
+    }
+
+    /// the same as `Endpoint::loopback_pair` but each endpoint receives packets out of order,
+    /// picking each one at random from the oldest `window` packets waiting, for testing how channels handle reordering
+    ///
+    /// the order only depends on the seed and the packets sent, so a failure can be reproduced,
+    /// a window of 1 doesn't reorder anything
+    pub fn loopback_pair_reordered(config_a: ClientConfig, config_b: ClientConfig, window: usize, seed: u64) -> Result<(Self, Self), Error> {
+        let (mut port_a, mut port_b) = loopback::pair();
+        port_a.reorder(window, seed);
+        port_b.reorder(window, seed.wrapping_add(1));
+
+        Ok((
+            Endpoint::from_transport(config_a, Transport::Loopback(port_a))?,
+            Endpoint::from_transport(config_b, Transport::Loopback(port_b))?,
+        ))
+    }
+
+    fn from_transport(config: ClientConfig, transport: Transport) -> Result<Self, Error> {
+        let checksum_len = if config.checksum {CHECKSUM_LEN} else {0};
+        if (config.max_message_size as usize) < Socket::HEARTBEAT_HEADER_LEN + checksum_len {
+            return Err(Error::MaxMessageSizeTooSmall);
+        }
+
+        if config.channels.len() > (CHALLENGE_PREFIX - CHANNEL_OFFSET) as usize {
+            return Err(Error::TooManyChannels);
+        }
+
+        for channel_config in config.channels.iter() {
+            channel_config.validate()?;
+        }
+
+        let socket = Socket::new(&config, transport);
+
+        let mut rng = Rng::new(config.seed);
+
+        Ok(Endpoint {
+            socket,
+
+            instance: rng.instance(),
+
+            challenge_key: RandomState::new(),
+            challenge_epoch: Instant::now(),
+
+            connections: HashMap::with_capacity(config.expected_connections),
+            recently_disconnected: HashSet::new(),
+
+            config,
+
+            accept_filter: None,
+
+            diagnostics: Diagnostics::default(),
+            taken_diagnostics: Diagnostics::default(),
+            connections_total: 0,
+
+            request_counter: 0,
+
+            events: Vec::new(),
+        })
+    }
+}
+
+impl<C: Default> Endpoint<C> {
+    /// changes the type of context held for each connection, any existing contexts are replaced with the default
+    pub fn with_context<D: Default>(self) -> Endpoint<D> {
+        Endpoint {
+            socket: self.socket,
+
+            instance: self.instance,
+
+            challenge_key: self.challenge_key,
+            challenge_epoch: self.challenge_epoch,
+
+            connections: self.connections.into_iter().map(|(addr, connection)| (addr, connection.with_context(D::default()))).collect(),
+            recently_disconnected: self.recently_disconnected,
+
+            config: self.config,
+
+            accept_filter: self.accept_filter,
+
+            diagnostics: self.diagnostics,
+            taken_diagnostics: self.taken_diagnostics,
+            connections_total: self.connections_total,
+
+            request_counter: self.request_counter,
+
+            events: self.events,
+        }
+    }
+
+    pub fn connect(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        self.connect_with_context(addr, C::default())
+    }
+
+    /// connects to an address with the given context instead of the default
+    pub fn connect_with_context(&mut self, addr: SocketAddr, context: C) -> Result<(), Error> {
+        let mut connection = Connection::new(&self.config, addr, &self.instance, &mut self.socket, context)?;
+        connection.unannounced = self.config.require_handshake_ack;
+
+        self.connections.insert(addr, connection);
+        self.recently_disconnected.remove(&addr);
+
+        if !self.config.require_handshake_ack {
+            self.events.push(Event::Connection(addr));
+            self.connections_total += 1;
+            self.events.push(Event::channels_ready(addr, &self.config.channels, self.socket.max_message_size));
+        }
+
+        Ok(())
+    }
+
+    /// connects to a peer behind a nat, for when both peers punch each other at around the same time
+    ///
+    /// heartbeats are sent every `interval` milliseconds until one is echoed back, as the first ones are likely
+    /// to be dropped before the other peer's nat has a mapping open
+    ///
+    /// `Event::Connection` is only emitted once an echo arrives, if `attempts` heartbeats go unechoed
+    /// the connection is dropped with `DisconnectReason::PunchFailed`
+    pub fn punch(&mut self, addr: SocketAddr, attempts: u32, interval: u128) -> Result<(), Error> {
+        let mut connection = Connection::new(&self.config, addr, &self.instance, &mut self.socket, C::default())?;
+
+        // creating the connection sent the first heartbeat
+        connection.punch = Some(Punch {
+            attempts_left: attempts.saturating_sub(1),
+            interval,
+            last_attempt: Instant::now(),
+        });
+
+        self.connections.insert(addr, connection);
+        self.recently_disconnected.remove(&addr);
+
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, addr: SocketAddr) -> Result<bool, Error> {
+        Ok(if self.remove_connection(addr, DisconnectReason::Kicked) {
+            self.socket.close(addr)?;
+
+            true
+        } else {
+            false
+        })
+    }
+
+    /// stops sending heartbeats, acks and resends to a connection and drops everything received from it, without disconnecting,
+    /// like for an app that's been backgrounded
+    ///
+    /// messages sent with `Endpoint::send` are held until `Endpoint::resume_connection`, other sends return `Error::ConnectionPaused`
+    ///
+    /// the connection doesn't time out while paused unless `ClientConfig::pause_timeout` is set,
+    /// but the peer still does unless it's timeout is long enough to cover the pause
+    pub fn pause_connection(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        connection.paused.get_or_insert_with(Instant::now);
+
+        Ok(())
+    }
+
+    /// resumes a connection paused with `Endpoint::pause_connection`, sending a heartbeat straight away followed by any held messages
+    ///
+    /// held messages that get a handle give an `Event::QueuedSent`, any that can't be sent give an `Event::SendFailed` without stopping the rest
+    ///
+    /// does nothing if the connection isn't paused
+    pub fn resume_connection(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+        if connection.paused.take().is_none() {return Ok(());}
+
+        // the pause isn't counted against the timeout
+        connection.last_received_keep_alive = Instant::now();
+
+        connection.heartbeat(&self.instance, &mut self.socket)?;
+
+        // messages from `Endpoint::send_or_connect` still wait for the handshake
+        if connection.heartbeat_echoed {
+            connection.send_queued(&mut self.socket, &mut self.events);
+        }
+
+        Ok(())
+    }
+
+    /// true if the connection has been paused with `Endpoint::pause_connection`
+    pub fn is_paused(&self, addr: SocketAddr) -> bool {
+        self.connections.get(&addr).is_some_and(|connection| connection.paused.is_some())
+    }
+
+    /// removes a connection without sending the peer a close, or anything else, giving an `Event::Disconnection` with `DisconnectReason::Dropped`
+    ///
+    /// meant for abusive peers that shouldn't get any response, the peer only finds out by timing out.
+    /// packets it keeps sending are treated like any from an unknown address, so a listening endpoint accepts it again
+    /// and one that refuses it, including through `Endpoint::set_accept_filter`, sends it a close,
+    /// only `ClientConfig::strict_peers` ignores it entirely
+    pub fn drop_connection(&mut self, addr: SocketAddr) -> bool {
+        self.forget_connection(addr, DisconnectReason::Dropped)
+    }
+
+    /// disconnects every connection, returning the result of notifying each address
+    ///
+    /// every connection is removed even if notifying some of them fails
+    pub fn disconnect_all(&mut self) -> Vec<(SocketAddr, Result<(), Error>)> {
+        let mut results = Vec::new();
+
+        let addrs: Vec<_> = self.connections.keys().copied().collect();
+
+        for addr in addrs {
+            self.remove_connection(addr, DisconnectReason::Kicked);
+            results.push((addr, self.socket.close(addr)));
+        }
+
+        results
+    }
+
+    /// keeps updating until every reliable and fec message has been acknowledged or the deadline passes,
+    /// then disconnects every connection
+    ///
+    /// events that happen while shutting down are discarded
+    pub fn shutdown(&mut self, deadline: Duration) -> Result<(), Error> {
+        let start = Instant::now();
+
+        while start.elapsed() < deadline && self.connections.values().any(|connection| connection.pending_messages() > 0) {
+            self.update()?;
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        for (_, result) in self.disconnect_all() {
+            result?;
+        }
+
+        self.events.clear();
+
+        Ok(())
+    }
+
+    /// keeps updating until the address has echoed back a heartbeat, see `Endpoint::is_reachable`,
+    /// returns false if the timeout passes or the connection is lost first
+    ///
+    /// events that happen while waiting are kept and returned by the next update
+    pub fn wait_connected(&mut self, addr: SocketAddr, timeout: Duration) -> Result<bool, Error> {
+        let start = Instant::now();
+
+        loop {
+            self.recently_disconnected.clear();
+
+            self.receive_packets(None)?;
+            self.maintain_connections()?;
+
+            if self.is_reachable(addr) {
+                return Ok(true);
+            }
+
+            if !self.connections.contains_key(&addr) || start.elapsed() >= timeout {
+                return Ok(false);
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// removes a connection and queues it's disconnection event, returns false if the address wasn't connected
+    ///
+    /// any acks the connection had queued are sent first so the peer doesn't keep resending it's last messages
+    fn remove_connection(&mut self, addr: SocketAddr, reason: DisconnectReason) -> bool {
+        if let Some(connection) = self.connections.get_mut(&addr) {
+            for channel in connection.channels.iter_mut() {
+                // the connection is being dropped either way, so failing to send the acks doesn't matter
+                let _ = channel.flush_acks(&mut self.socket);
+            }
+        }
+
+        self.forget_connection(addr, reason)
+    }
+
+    /// removes a connection without sending anything to it, not even held back acks
+    fn forget_connection(&mut self, addr: SocketAddr, reason: DisconnectReason) -> bool {
+        let Some(connection) = self.connections.remove(&addr) else {return false;};
+
+        for channel in connection.channels.iter() {
+            self.diagnostics.merge(&channel.diagnostics);
+        }
+
+        for (channel_id, message) in connection.queued_sends {
+            self.events.push(Event::Unsent { addr, channel_id, message });
+        }
+
+        self.recently_disconnected.insert(addr);
+        self.events.push(Event::Disconnection(addr, reason));
+
+        true
+    }
+
+    /// handles a single received packet, queueing any events it causes
+    fn handle_packet(&mut self, mut message: Vec<u8>, origin: SocketAddr) -> Result<(), Error> {
+        if self.config.checksum {
+            let Some(packet_len) = wire::verify_checksum(&message).map(<[u8]>::len) else {
+                self.diagnostics.corrupt_packets += 1;
+
+                if self.config.report_invalid_packets {
+                    self.events.push(Event::InvalidPacket { from: origin, reason: InvalidReason::ChecksumMismatch });
+                }
+
+                return Ok(());
+            };
+
+            message.truncate(packet_len);
+        }
+
+        if self.config.strict_peers && !self.connections.contains_key(&origin) {
+            return Ok(());
+        }
+
+        let mut channel_message = None;
+        let mut heartbeat_data: Option<([u8; 16], [u8; 16], Vec<u8>)> = None;
+        let mut time_response = None;
+        let mut invalid_reason = None;
+        let mut challenge_answered = false;
+
+        let valid_message = match message.first().copied() {
+            None => {
+                invalid_reason = Some(InvalidReason::Empty);
+                false
+            },
+            Some(0) => {
+
+                let heartbeat = message[1..].split_first_chunk::<16>().and_then(|(instance, rest)| {
+                    let (time, payload) = rest.split_first_chunk::<16>()?;
+                    Some((*instance, *time, payload.to_vec()))
+                });
+
+                if let Some(heartbeat) = heartbeat {
+                    heartbeat_data = Some(heartbeat);
+                    true
+                } else {
+                    invalid_reason = Some(InvalidReason::Truncated);
+                    false
+                }
+            },
+            Some(1) => {
+                // a peer that isn't listening closes heartbeats that arrive before it has started punching
+                if self.connections.get(&origin).is_none_or(|connection| connection.punch.is_none()) {
+                    self.remove_connection(origin, DisconnectReason::Other);
+                }
+
+                false
+            },
+            Some(2) => {
+
+                if let Some((time, rest)) = message[1..].split_first_chunk::<16>() {
+                    // the other client's system time is optional
+                    let remote_time = rest.first_chunk::<16>().map(|bytes| u128::from_be_bytes(*bytes));
+
+                    time_response = Some((u128::from_be_bytes(*time), remote_time));
+                    true
+                } else {
+                    invalid_reason = Some(InvalidReason::Truncated);
+                    false
+                }
+            },
+            Some(RAW_PREFIX) => {
+                // raw packets are outside of connections, so they don't create or keep one alive
+                self.events.push(Event::Raw(origin, message.split_off(1)));
+                false
+            },
+            Some(DISCOVERY_PREFIX) => {
+                self.events.push(Event::Discovery(origin, message.split_off(1)));
+                false
+            },
+            Some(CHALLENGE_PREFIX) => 'b: {
+                let Some((&answer, token)) = message[1..].split_first().and_then(|(answer, rest)| Some((answer, u64::from_be_bytes(*rest.first_chunk::<8>()?)))) else {
+                    invalid_reason = Some(InvalidReason::Truncated);
+                    break 'b false;
+                };
+
+                if answer == 0 {
+                    // only answered for a connection, so it can't be used to reflect packets at someone else,
+                    // still answered once connected so a peer can challenge this endpoint after it changes address
+                    let Some(connection) = self.connections.get_mut(&origin) else {break 'b false;};
+
+                    self.socket.challenge(origin, true, token)?;
+
+                    if !connection.heartbeat_echoed {
+                        connection.heartbeat(&self.instance, &mut self.socket)?;
+                    }
+
+                    false
+                } else {
+                    // a token from the last interval is still accepted, so one sent just before the interval changed can be answered
+                    let interval = self.challenge_epoch.elapsed().as_millis() / CHALLENGE_INTERVAL;
+                    challenge_answered = !self.connections.contains_key(&origin)
+                        && [Some(interval), interval.checked_sub(1)].into_iter().flatten().any(|interval| self.challenge_key.hash_one((origin, interval)) == token);
+
+                    challenge_answered
+                }
+            },
+            Some(channel_id) => {
+                let channel_id = channel_id - CHANNEL_OFFSET;
+                match self.config.channels.get(channel_id as usize) {
+                    None => {
+                        invalid_reason = Some(InvalidReason::UnknownChannel);
+                        false
+                    },
+                    Some(channel_config) if channel_config.is_send_only() => {
+                        invalid_reason = Some(InvalidReason::DirectionMismatch);
+                        false
+                    },
+                    Some(channel_config) if message.len() - 1 < channel_config.min_packet_len() => {
+                        invalid_reason = Some(InvalidReason::Truncated);
+                        false
+                    },
+                    Some(_) => {
+                        // remove the channel id
+                        message.drain(..1);
+                        channel_message = Some((channel_id, message));
+                        true
+                    },
+                }
+            },
+        };
+
+        if let Some(reason) = invalid_reason {
+            self.diagnostics.invalid_packets += 1;
+
+            if self.config.report_invalid_packets {
+                self.events.push(Event::InvalidPacket { from: origin, reason });
+            }
+        }
+
+        if valid_message {
+            // a new address has to answer a challenge first, see `ClientConfig::challenge_handshake`,
+            // before it can make a connection or take one over with `DuplicateInstance::Migrate`
+            if self.config.listen && self.config.challenge_handshake && !challenge_answered && !self.connections.contains_key(&origin) {
+                if heartbeat_data.is_some() {
+                    let interval = self.challenge_epoch.elapsed().as_millis() / CHALLENGE_INTERVAL;
+                    self.socket.challenge(origin, false, self.challenge_key.hash_one((origin, interval)))?;
+                }
+
+                return Ok(());
+            }
+
+            if let (Some((instance, _, _)), Some(duplicate_instances)) = (&heartbeat_data, self.config.duplicate_instances) {
+                if self.handle_duplicate_instance(instance, origin, duplicate_instances)? {
+                    return Ok(());
+                }
+            }
+
+            let connection = match self.connections.entry(origin) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    if self.config.listen && self.accept_filter.as_mut().is_none_or(|accept_filter| accept_filter(origin)) {
+                        let connection = entry.insert(Connection::new(&self.config, origin, &self.instance, &mut self.socket, C::default())?);
+                        self.recently_disconnected.remove(&origin);
+                        self.events.push(Event::Connection(origin));
+                        self.connections_total += 1;
+                        self.events.push(Event::channels_ready(origin, &self.config.channels, self.socket.max_message_size));
+                        connection
+                    } else {
+                        self.socket.close(origin)?;
+                        return Ok(());
+                    }
+                },
+            };
+
+            // everything from a paused connection is dropped, the peer resends anything reliable once it's resumed
+            if connection.paused.is_some() {
+                return Ok(());
+            }
+
+            connection.last_received_keep_alive = Instant::now();
+
+            if connection.stalled {
+                connection.stalled = false;
+                self.events.push(Event::ConnectionRecovered(origin));
+            }
+
+            if let Some((channel_id, message)) = channel_message {
+                if let Some(channel) = connection.channels.get_mut(channel_id as usize) {
+                    let received = channel.receive(message, connection.average_ping, &mut self.socket)?;
+
+                    // gaps come before the messages that revealed or filled them
+                    for gap in channel.gaps.drain(..) {
+                        self.events.push(match gap {
+                            GapChange::Missing(missing_seqs) => Event::Gap { addr: origin, channel_id, missing_seqs },
+                            GapChange::Filled(seq) => Event::GapFilled { addr: origin, channel_id, seq },
+                        });
+                    }
+
+                    for (RequestHeader { id, response }, message) in channel.requests.drain(..) {
+                        let request_id = RequestId { addr: origin, id };
+
+                        self.events.push(if response {
+                            Event::Response(request_id, message)
+                        } else {
+                            Event::Request { channel_id, request_id, message }
+                        });
+                    }
+
+                    for change in channel.streams.drain(..) {
+                        self.events.push(match change {
+                            StreamChange::Progress { stream_id, received_chunks, total_chunks } => Event::StreamProgress { addr: origin, channel_id, stream_id, received_chunks, total_chunks },
+                            StreamChange::Complete { stream_id, data } => Event::Stream { addr: origin, channel_id, stream_id, data },
+                        });
+                    }
+
+                    for received in received {
+                        self.events.push(Event::received(origin, channel_id, received, self.config.sequenced_messages));
+                    }
+
+                    for seq in channel.delivered.drain(..) {
+                        self.events.push(Event::Delivered(origin, MessageHandle { channel_id, seq }));
+                    }
+
+                    if channel.writable() {
+                        self.events.push(Event::ChannelWritable(origin, channel_id));
+                    }
+
+                    // acks that came with the message are handled as if they arrived on their own
+                    for PiggybackedAck { channel_id, ack } in std::mem::take(&mut channel.received_acks) {
+                        let Some(channel) = connection.channels.get_mut(channel_id as usize) else {continue;};
+                        if !matches!(channel.channel_type, ChannelType::SendReliable { .. }) {continue;}
+
+                        channel.receive(ack.encode().to_vec(), connection.average_ping, &mut self.socket)?;
+
+                        for seq in channel.delivered.drain(..) {
+                            self.events.push(Event::Delivered(origin, MessageHandle { channel_id, seq }));
+                        }
+
+                        if channel.writable() {
+                            self.events.push(Event::ChannelWritable(origin, channel_id));
+                        }
+                    }
+                }
+            }
+
+            if let Some((time, remote_time)) = time_response {
+                // an echo of a time that hasn't been sent yet can only come from a misbehaving peer
+                let Some(diff) = connection.creation_time.elapsed().as_millis().checked_sub(time) else {return Ok(());};
+
+                connection.heartbeat_echoed = true;
+
+                let unannounced = std::mem::take(&mut connection.unannounced);
+
+                if connection.punch.take().is_some() || unannounced {
+                    self.events.push(Event::Connection(origin));
+                    self.connections_total += 1;
+                    self.events.push(Event::channels_ready(origin, &self.config.channels, self.socket.max_message_size));
+                }
+
+                connection.send_queued(&mut self.socket, &mut self.events);
+
+                // estimate the clock offset from the sample with the lowest round trip,
+                // as it has the least room for asymmetric latency
+                if let Some(remote_time) = remote_time {
+                    if connection.min_ping.is_none_or(|min_ping| diff <= min_ping) {
+                        connection.min_ping = Some(diff);
+
+                        let local_time = system_time_millis() as i128 - diff as i128 / 2;
+                        connection.clock_offset = Some((remote_time as i128).saturating_sub(local_time));
+                    }
+                }
+
+                if connection.ping_memory.len() >= self.config.ping_memory_length as usize {
+                    connection.ping_memory.pop_front();
+                }
+                connection.ping_memory.push_back(diff);
+
+                let average_ping = match self.config.ping_smoothing {
+                    Some(ping_smoothing) => {
+                        let ping_smoothing = ping_smoothing.clamp(0., 1.) as f64;
+                        let smoothed_ping = connection.smoothed_ping.map_or(diff as f64, |smoothed_ping| smoothed_ping + (diff as f64 - smoothed_ping) * ping_smoothing);
+                        connection.smoothed_ping = Some(smoothed_ping);
+                        smoothed_ping.round() as u128
+                    },
+                    None => connection.ping_memory.iter().sum::<u128>() / connection.ping_memory.len() as u128,
+                };
+
+                // the memory is never longer than it's length, so that's the most samples that can be waited for
+                let min_ping_samples = self.config.min_ping_samples.unwrap_or(1).min(self.config.ping_memory_length).max(1);
+                let established = connection.ping_memory.len() >= min_ping_samples as usize;
+
+                if connection.average_ping.is_none() && established {
+                    self.events.push(Event::PingEstablished(origin, average_ping));
+                }
+
+                if let Some(index) = connection.pings.iter().position(|&ping_time| ping_time == time) {
+                    connection.pings.swap_remove(index);
+                    self.events.push(Event::Pong(origin, diff));
+                }
+
+                // pings that were never echoed
+                let now = connection.creation_time.elapsed().as_millis();
+                let timeout = connection.timeout;
+                connection.pings.retain(|&ping_time| now - ping_time <= timeout);
+
+                if established {
+                    connection.average_ping = Some(average_ping);
+                }
+            }
+
+            if let Some((instance, time, payload)) = heartbeat_data {
+                match connection.other_instance {
+                    None => connection.other_instance = Some(instance),
+                    Some(other_instance) => if instance != other_instance {
+                        self.remove_connection(origin, DisconnectReason::OriginChangedInstance);
+                    }
+                }
+
+                if !payload.is_empty() && self.connections.contains_key(&origin) {
+                    self.events.push(Event::HeartbeatPayload(origin, payload));
+                }
+
+                self.socket.clear_buffer();
+                self.socket.write(&[2])?;
+                self.socket.write(&time)?;
+                self.socket.write(&system_time_millis().to_be_bytes())?;
+                self.socket.send(origin)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// checks a heartbeat from an address that hasn't sent one yet against the instances of other connections,
+    /// returning true if the packet should be dropped
+    fn handle_duplicate_instance(&mut self, instance: &[u8; 16], origin: SocketAddr, duplicate_instances: DuplicateInstance) -> Result<bool, Error> {
+        if self.connections.get(&origin).is_some_and(|connection| connection.other_instance.is_some()) {
+            return Ok(false);
+        }
+
+        let Some(from) = self.connections.iter()
+            .find(|(addr, connection)| **addr != origin && connection.other_instance == Some(*instance))
+            .map(|(addr, _)| *addr)
+        else {return Ok(false);};
+
+        match duplicate_instances {
+            DuplicateInstance::Migrate => {
+                let Some(mut connection) = self.connections.remove(&from) else {return Ok(false);};
+                connection.migrate(origin);
+
+                // replaces any connection made by packets from the new address before this heartbeat,
+                // nothing is sent to it since the address now belongs to the migrated connection
+                self.forget_connection(origin, DisconnectReason::Replaced);
+                self.connections.insert(origin, connection);
+                self.recently_disconnected.remove(&origin);
+                self.events.push(Event::ConnectionMigrated { from, to: origin });
+
+                Ok(false)
+            },
+            DuplicateInstance::Reject => {
+                self.remove_connection(origin, DisconnectReason::DuplicateInstance);
+                self.socket.close(origin)?;
+
+                Ok(true)
+            },
+        }
+    }
+
+    /// handles a packet as if it was received from `origin`, used by the fuzz targets
+    #[cfg(feature = "fuzzing")]
+    #[doc(hidden)]
+    pub fn fuzz_packet(&mut self, packet: &[u8], origin: SocketAddr) -> Result<(), Error> {
+        self.handle_packet(Vec::from(packet), origin)
+    }
+
+    /// queues a packet to be received from `from` on the next update, before anything that actually arrived
+    ///
+    /// it goes through everything a received packet does, including checksums, `ClientConfig::max_packets_per_update`
+    /// and the metrics, so tests can send malformed packets or exact sequences of them without a socket or timing involved
+    #[cfg(any(test, feature = "testing"))]
+    pub fn inject_raw(&mut self, from: SocketAddr, bytes: &[u8]) {
+        self.socket.injected.push_back((Vec::from(bytes), from));
+    }
+
+    pub fn update(&mut self) -> Result<Vec<Event>, Error> {
+        self.update_with_budget(None)
+    }
+
+    /// the same as `Endpoint::update` but stops receiving packets once the budget has been used,
+    /// leaving the rest to be received on the next update
+    ///
+    /// the budget is checked between packets, so at least one packet is handled and a slow packet can go over it,
+    /// timeouts, heartbeats and resends are still done after the budget runs out
+    pub fn update_bounded(&mut self, budget: Duration) -> Result<Vec<Event>, Error> {
+        self.update_with_budget(Some(budget))
+    }
+
+    /// only the receiving half of `Endpoint::update`, handling every packet available without sending heartbeats,
+    /// timing out connections or resending messages
+    ///
+    /// for driving the two halves at different rates, with `Endpoint::maintain` for the rest
+    pub fn receive_events(&mut self) -> Result<Vec<Event>, Error> {
+        self.recently_disconnected.clear();
+
+        self.receive_packets(None)?;
+
+        Ok(std::mem::take(&mut self.events))
+    }
+
+    /// only the maintenance half of `Endpoint::update`, sending heartbeats, timing out connections and resending messages
+    /// without receiving anything, see `Endpoint::receive_events`
+    pub fn maintain(&mut self) -> Result<Vec<Event>, Error> {
+        self.recently_disconnected.clear();
+
+        self.maintain_connections()?;
+
+        Ok(std::mem::take(&mut self.events))
+    }
+
+    fn update_with_budget(&mut self, budget: Option<Duration>) -> Result<Vec<Event>, Error> {
+        self.recently_disconnected.clear();
+
+        self.receive_packets(budget)?;
+        self.maintain_connections()?;
+
+        Ok(std::mem::take(&mut self.events))
+    }
+
+    /// receives and handles packets until there are none left, the max packets per update is reached or the budget runs out
+    fn receive_packets(&mut self, budget: Option<Duration>) -> Result<(), Error> {
+        let start = Instant::now();
+
+        let mut packets_processed = 0;
+
+        loop {
+            if let Some(max_packets) = self.config.max_packets_per_update {
+                if packets_processed >= max_packets {break;}
+            }
+
+            if packets_processed > 0 && budget.is_some_and(|budget| start.elapsed() >= budget) {
+                break;
+            }
+
+            let Some((message, origin)) = self.socket.receive()? else {break;};
+            let message = Vec::from(message);
+            packets_processed += 1;
+
+            self.handle_packet(message, origin)?;
+        }
+
+        Ok(())
+    }
+
+    /// sends heartbeats and resends, and removes connections that have timed out, failed or become unreachable
+    fn maintain_connections(&mut self) -> Result<(), Error> {
+        self.socket.retry_sends()?;
+
+        // punched connections are expected to be unreachable until the other peer has punched back
+        for addr in std::mem::take(&mut self.socket.unreachable) {
+            if self.connections.get(&addr).is_some_and(|connection| connection.punch.is_none()) {
+                self.remove_connection(addr, DisconnectReason::PortUnreachable);
+            }
+        }
+
+        // timeout clients
+        let mut to_remove = Vec::new();
+
+        for (&origin, connection) in self.connections.iter_mut() {
+            if let Some(paused) = connection.paused {
+                if self.config.pause_timeout.is_some_and(|pause_timeout| paused.elapsed().as_millis() > pause_timeout) {
+                    to_remove.push((origin, DisconnectReason::Timeout));
+                }
+            } else if let Some(punch) = connection.punch {
+                if punch.last_attempt.elapsed().as_millis() >= punch.interval {
+                    if punch.attempts_left == 0 {
+                        to_remove.push((origin, DisconnectReason::PunchFailed));
+                    } else {
+                        connection.heartbeat(&self.instance, &mut self.socket)?;
+                        connection.punch = Some(Punch {
+                            attempts_left: punch.attempts_left - 1,
+                            last_attempt: Instant::now(),
+                            ..punch
+                        });
+                    }
+                }
+            } else if !connection.heartbeat_echoed && connection.creation_time.elapsed().as_millis() > self.config.handshake_timeout {
+                to_remove.push((origin, DisconnectReason::HandshakeTimeout));
+            } else if connection.last_received_keep_alive.elapsed().as_millis() > connection.timeout {
+                to_remove.push((origin, DisconnectReason::Timeout));
+            } else if let Some(stall_fraction) = self.config.stall_fraction {
+                if connection.heartbeat_echoed && !connection.stalled && connection.last_received_keep_alive.elapsed().as_millis() as f32 > connection.timeout as f32 * stall_fraction {
+                    connection.stalled = true;
+                    self.events.push(Event::ConnectionStalled(origin));
+                }
+            }
+        }
+
+        for (addr, reason) in to_remove {
+            self.remove_connection(addr, reason);
+        }
+
+
+        // update clients
+        let mut failed = Vec::new();
+
+        for (&addr, connection) in self.connections.iter_mut() {
+            if connection.paused.is_some() {continue;}
+
+            connection.update(&self.instance, &mut self.socket)?;
+
+            for channel in connection.channels.iter_mut() {
+                // messages held by a jitter buffer for long enough
+                for received in channel.release_held() {
+                    self.events.push(Event::received(addr, channel.channel_id, received, self.config.sequenced_messages));
+                }
+
+                // expired messages
+                if channel.writable() {
+                    self.events.push(Event::ChannelWritable(addr, channel.channel_id));
+                }
+            }
+
+            if connection.channels.iter().any(|channel| channel.failed) {
+                failed.push(addr);
+            }
+        }
+
+        for addr in failed {
+            self.remove_connection(addr, DisconnectReason::ReliableFailure);
+        }
+
+        Ok(())
+    }
+
+    /// updates the endpoint and passes each event to a handler one at a time, so the handler can send or disconnect in response
+    ///
+    /// if the handler breaks, the events it didn't get are held until the next update
+    pub fn for_each_event(&mut self, f: impl FnMut(&mut Endpoint<C>, Event) -> ControlFlow<()>) -> Result<(), Error> {
+        handle_events(self, |endpoint| endpoint, f)
+    }
+
+    /// removes and returns the queued events for a given address, leaving the rest queued
+    ///
+    /// events queued by `Endpoint::connect` and `Endpoint::disconnect` are held until the next `Endpoint::update`
+    pub fn drain_events_for(&mut self, addr: SocketAddr) -> Vec<Event> {
+        let (events, remaining) = std::mem::take(&mut self.events).into_iter().partition(|event| event.addr() == addr);
+        self.events = remaining;
+        events
+    }
+
+    /// sends a message, returning a handle on reliable and fec channels that will be in an `Event::Delivered` once the message is acknowledged
+    pub fn send(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<Option<MessageHandle>, Error> {
+        self.send_priority(addr, channel_id, message, 0)
+    }
+
+    /// sends a message with a priority, higher priority messages are retransmitted before lower priority ones
+    ///
+    /// only affects the order messages are resent in on reliable and fec channels, the message is sent immediately either way
+    ///
+    /// messages to a paused connection are checked like any other and held until it's resumed,
+    /// their handle comes in an `Event::QueuedSent` once they're sent, see `Endpoint::pause_connection`
+    pub fn send_priority(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8], priority: u8) -> Result<Option<MessageHandle>, Error> {
+        if self.connections.get(&addr).is_some_and(|connection| connection.paused.is_some()) {
+            self.check_send(addr, channel_id, message)?;
+
+            if let Some(connection) = self.connections.get_mut(&addr) {
+                connection.queued_sends.push((channel_id, message.to_vec()));
+            }
+
+            return Ok(None);
+        }
+
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return Err(if self.recently_disconnected.contains(&addr) {Error::ConnectionClosing} else {Error::AddressNotConnected});
+        };
+
+        let Some(channel) = connection.channels.get(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        let piggyback = if self.config.piggyback_acks && matches!(channel.channel_type, ChannelType::SendReliable { .. }) {connection.take_ack()} else {None};
+
+        let channel = &mut connection.channels[channel_id as usize];
+        channel.piggyback = piggyback;
+
+        let seq = channel.send(message, priority, &mut self.socket);
+
+        if channel.writable() {
+            self.events.push(Event::ChannelWritable(addr, channel_id));
+        }
+
+        // the message was deferred or had no room for the acks
+        if let Some(piggyback) = channel.piggyback.take() {
+            connection.requeue_ack(piggyback);
+        }
+
+        Ok(seq?.map(|seq| MessageHandle { channel_id, seq }))
+    }
+
+    /// everything that would make `Endpoint::send` fail short of the socket itself, checked without sending anything
+    fn check_send(&self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<(), Error> {
+        let Some(connection) = self.connections.get(&addr) else {
+            return Err(if self.recently_disconnected.contains(&addr) {Error::ConnectionClosing} else {Error::AddressNotConnected});
+        };
+
+        let Some(channel) = connection.channels.get(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        if message.len() > self.max_payload(channel_id)? {
+            return Err(Error::MessageTooLong);
+        }
+
+        if !channel.has_sequences_for(message.len(), self.socket.max_message_size) {
+            return Err(Error::SequencesExhausted);
+        }
+
+        Ok(())
+    }
+
+    /// sends the same message on several channels, like unreliably for low latency with a reliable channel as a backup,
+    /// returning the handle from each channel in the same order
+    ///
+    /// every channel is checked before sending, so if the connection is gone, a channel id is bad, the message is too long
+    /// or a channel is out of sequence numbers nothing is sent, only an io error part way through can leave it sent on some
+    pub fn send_multi(&mut self, addr: SocketAddr, channel_ids: &[u8], message: &[u8]) -> Result<Vec<Option<MessageHandle>>, Error> {
+        for &channel_id in channel_ids {
+            self.check_send(addr, channel_id, message)?;
+        }
+
+        channel_ids.iter().map(|&channel_id| self.send(addr, channel_id, message)).collect()
+    }
+
+    /// adds a channel after binding, giving it the next channel id on every existing and future connection
+    ///
+    /// the peer has to add the opposite channel at the same id, until then it will drop packets on the channel as `InvalidReason::UnknownChannel`
+    pub fn add_channel(&mut self, channel_config: ChannelConfig) -> Result<u8, Error> {
+        if self.config.channels.len() >= (CHALLENGE_PREFIX - CHANNEL_OFFSET) as usize {
+            return Err(Error::TooManyChannels);
+        }
+
+        channel_config.validate()?;
+
+        let channel_id = self.config.channels.len() as u8;
+
+        for (&addr, connection) in self.connections.iter_mut() {
+            connection.channels.push(Channel::new(&channel_config, channel_id, addr, &self.config));
+        }
+
+        self.config.channels.push(channel_config);
+
+        Ok(channel_id)
+    }
+
+    /// sends a message, connecting to the address first if it isn't connected
+    ///
+    /// until the connection has had a heartbeat echoed back the message is queued, then sent as if by `Endpoint::send`,
+    /// if the connection is dropped before then the message is given back in an `Event::Unsent`
+    pub fn send_or_connect(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<(), Error> {
+        // check the message can be sent now rather than when the connection is usable
+        if message.len() > self.max_payload(channel_id)? {
+            return Err(Error::MessageTooLong);
+        }
+
+        if !self.connections.contains_key(&addr) {
+            self.connect(addr)?;
+        }
+
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        if connection.heartbeat_echoed {
+            self.send(addr, channel_id, message)?;
+        } else {
+            connection.queued_sends.push((channel_id, message.to_vec()));
+        }
+
+        Ok(())
+    }
+
+    /// sends a message on a `SendUnreliable` channel that's dropped if it's still held back by the rate limit after `ttl` milliseconds,
+    /// so stale data isn't sent late
+    pub fn send_with_ttl(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8], ttl: u128) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return Err(if self.recently_disconnected.contains(&addr) {Error::ConnectionClosing} else {Error::AddressNotConnected});
+        };
+
+        if connection.paused.is_some() {
+            return Err(Error::ConnectionPaused);
+        }
+
+        let Some(channel) = connection.channels.get_mut(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        // a ttl too long to represent never expires
+        let expires = Instant::now().checked_add(Duration::from_millis(ttl.min(u64::MAX as u128) as u64));
+
+        channel.queue_unreliable(message, expires, &mut self.socket)
+    }
+
+    /// reads everything from `reader` and sends it on a `SendReliable` channel, split into as many messages as it takes,
+    /// returning an id for the stream
+    ///
+    /// the peer needs a `ChannelConfig::ReceiveReliableStream` at the same channel id to put it back together,
+    /// where it arrives as an `Event::Stream` with the same id
+    pub fn send_stream(&mut self, addr: SocketAddr, channel_id: u8, mut reader: impl Read) -> Result<u32, Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return Err(if self.recently_disconnected.contains(&addr) {Error::ConnectionClosing} else {Error::AddressNotConnected});
+        };
+
+        if connection.paused.is_some() {
+            return Err(Error::ConnectionPaused);
+        }
+
+        let Some(channel) = connection.channels.get_mut(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let stream_id = channel.send_stream(&data, &mut self.socket);
+
+        if channel.writable() {
+            self.events.push(Event::ChannelWritable(addr, channel_id));
+        }
+
+        stream_id
+    }
+
+    /// sends a request on a `SendReliable` channel, returning an id that the `Event::Response` to it will have
+    ///
+    /// the peer needs a `ChannelConfig::ReceiveReliableRequests` at the same channel id to get it as an `Event::Request`,
+    /// and the request takes up `9` bytes of the channel's max payload
+    pub fn request(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<RequestId, Error> {
+        let id = self.request_counter;
+
+        self.send_request(addr, channel_id, RequestHeader { id, response: false }, message)?;
+
+        // a failed request doesn't use up an id
+        self.request_counter = self.request_counter.wrapping_add(1);
+
+        Ok(RequestId { addr, id })
+    }
+
+    /// answers an `Event::Request` on a `SendReliable` channel, arriving at the requester as an `Event::Response`
+    ///
+    /// the requester needs a `ChannelConfig::ReceiveReliableRequests` at the same channel id
+    pub fn respond(&mut self, request_id: RequestId, channel_id: u8, message: &[u8]) -> Result<(), Error> {
+        self.send_request(request_id.addr, channel_id, RequestHeader { id: request_id.id, response: true }, message)
+    }
+
+    fn send_request(&mut self, addr: SocketAddr, channel_id: u8, header: RequestHeader, message: &[u8]) -> Result<(), Error> {
+        let Some(channel_config) = self.config.channels.get(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+        if !matches!(channel_config, ChannelConfig::SendReliable { .. }) {return Err(Error::NotReliableChannel);}
+
+        let mut request = Vec::with_capacity(RequestHeader::LEN + message.len());
+        request.extend_from_slice(&header.encode());
+        request.extend_from_slice(message);
+
+        self.send(addr, channel_id, &request)?;
+
+        Ok(())
+    }
+
+    /// sends bytes to any address outside of the channels and connections, arriving as an `Event::Raw`
+    ///
+    /// the packet is only prefixed by a single reserved byte, `0xFF`, so it can carry custom control messages
+    /// or be picked out by another protocol sharing the socket
+    pub fn send_raw(&mut self, addr: SocketAddr, bytes: &[u8]) -> Result<(), Error> {
+        self.socket.raw(addr, bytes)
+    }
+
+    /// broadcasts a discovery probe to every ipv4 host on the local network at the port, arriving as an `Event::Discovery`
+    ///
+    /// enables `SO_BROADCAST` on the socket the first time, so it returns `Error::NoSocket` for loopback endpoints
+    /// and fails on sockets bound to an ipv6 address, which has no broadcast
+    pub fn send_broadcast(&mut self, port: u16, payload: &[u8]) -> Result<(), Error> {
+        let socket = self.socket.udp()?;
+        if !socket.broadcast()? {
+            socket.set_broadcast(true)?;
+        }
+
+        self.socket.discovery(SocketAddr::from((std::net::Ipv4Addr::BROADCAST, port)), payload)
+    }
+
+    /// sends a discovery packet to a single address, usually to answer an `Event::Discovery` from `Endpoint::send_broadcast`
+    ///
+    /// like `Endpoint::send_raw` it doesn't need a connection, but it arrives as an `Event::Discovery`
+    pub fn send_discovery(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<(), Error> {
+        self.socket.discovery(addr, payload)
+    }
+
+    /// sends many messages on a `SendUnreliableBatched` channel, packing them into as few packets as possible
+    ///
+    /// nothing is sent if any of the messages is too long
+    pub fn send_batch(&mut self, addr: SocketAddr, channel_id: u8, messages: &[&[u8]]) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return Err(if self.recently_disconnected.contains(&addr) {Error::ConnectionClosing} else {Error::AddressNotConnected});
+        };
+
+        if connection.paused.is_some() {
+            return Err(Error::ConnectionPaused);
+        }
+
+        let Some(channel) = connection.channels.get_mut(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        channel.send_batch(messages, &mut self.socket)
+    }
+
+    /// sends a message to every connection, returning the result of sending to each address
+    pub fn broadcast(&mut self, channel_id: u8, message: &[u8]) -> Vec<(SocketAddr, Result<Option<MessageHandle>, Error>)> {
+        let addrs: Vec<_> = self.connections.keys().copied().collect();
+
+        addrs.into_iter().map(|addr| (addr, self.send(addr, channel_id, message))).collect()
+    }
+
+    /// sends a string as utf-8 bytes, see `Event::message_str` for receiving
+    pub fn send_str(&mut self, addr: SocketAddr, channel_id: u8, message: &str) -> Result<Option<MessageHandle>, Error> {
+        self.send(addr, channel_id, message.as_bytes())
+    }
+
+    pub fn send_single(&mut self, channel_id: u8, message: &[u8]) -> Result<Option<MessageHandle>, Error> {
+        let mut addresses = self.connections.keys();
+        match (addresses.next(), addresses.next()) {
+            (None, None) => Err(Error::SendSingleInvalid),
+            (Some(_), Some(_)) => Err(Error::SendSingleInvalid),
+            (Some(&addr), None) => self.send(addr, channel_id, message),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get_ping(&self, connection: SocketAddr) -> Result<Option<u128>, Error> {
+        self.connections.get(&connection).ok_or(Error::AddressNotConnected).map(|connection| connection.average_ping)
+    }
+
+    /// gets the context of a connection, `None` if the address isn't connected
+    pub fn context(&self, addr: SocketAddr) -> Option<&C> {
+        self.connections.get(&addr).map(|connection| &connection.context)
+    }
+
+    pub fn context_mut(&mut self, addr: SocketAddr) -> Option<&mut C> {
+        self.connections.get_mut(&addr).map(|connection| &mut connection.context)
+    }
+
+    /// sends a heartbeat immediately instead of waiting for the heartbeat interval
+    ///
+    /// useful for quickly measuring ping again, for example after the network recovers
+    pub fn ping_now(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        connection.heartbeat(&self.instance, &mut self.socket)?;
+
+        Ok(())
+    }
+
+    /// sends a heartbeat that gives an `Event::Pong` with the round trip time once it's echoed back
+    ///
+    /// the round trip also counts towards the connection's average ping like any other heartbeat
+    pub fn send_ping(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        let time = connection.heartbeat(&self.instance, &mut self.socket)?;
+        connection.pings.push(time);
+
+        Ok(())
+    }
+
+    /// overrides the timeout set in the `ClientConfig` for a single connection
+    pub fn set_connection_timeout(&mut self, addr: SocketAddr, timeout: u128) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        connection.timeout = timeout;
+
+        Ok(())
+    }
+
+    /// overrides the heartbeat interval set in the `ClientConfig` for a single connection, 0 stops sending heartbeats
+    pub fn set_heartbeat_interval(&mut self, addr: SocketAddr, heartbeat_interval: u128) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        connection.heartbeat_interval = heartbeat_interval;
+
+        Ok(())
+    }
+
+    pub fn channel_stats(&self, addr: SocketAddr, channel_id: u8) -> Result<ChannelStats, Error> {
+        let Some(connection) = self.connections.get(&addr) else {return Err(Error::AddressNotConnected);};
+
+        let Some(channel) = connection.channels.get(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        let mut stats = channel.stats.clone();
+
+        stats.resend_threshhold = match &channel.channel_type {
+            ChannelType::SendReliable { resend_threshhold, .. } => Some(*resend_threshhold),
+            ChannelType::SendFecReliable { resend_threshhold, .. } => Some(*resend_threshhold),
+            _ => None,
+        };
+
+        Ok(stats)
+    }
+
+    /// number of distinct sequences a receive reliable channel has yet to acknowledge, counting a sequence received twice once
+    ///
+    /// they're coalesced into as few ack packets as possible when sent, see `Endpoint::flush_acks`, always 0 for other channel types
+    pub fn pending_acks(&self, addr: SocketAddr, channel_id: u8) -> Result<usize, Error> {
+        let Some(connection) = self.connections.get(&addr) else {return Err(Error::AddressNotConnected);};
+
+        let Some(channel) = connection.channels.get(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        Ok(match &channel.channel_type {
+            ChannelType::ReceiveReliable { acks_to_send, .. } => acks_to_send.iter().collect::<HashSet<_>>().len(),
+            _ => 0,
+        })
+    }
+
+    /// bytes queued on a connection's channels that haven't been sent for the first time yet, because of the channels' rate limits
+    ///
+    /// sent messages waiting for acknowledgement aren't counted, see `Endpoint::debug_channel_state` for those
+    pub fn outbound_queue_len(&self, addr: SocketAddr) -> Result<usize, Error> {
+        let Some(connection) = self.connections.get(&addr) else {return Err(Error::AddressNotConnected);};
+
+        Ok(connection.channels.iter().map(Channel::queued_bytes).sum())
+    }
+
+    /// sets bytes to send in every heartbeat, for small liveness info like load or the current tick without needing a channel,
+    /// they're given to the other side in an `Event::HeartbeatPayload`
+    ///
+    /// an empty payload sends heartbeats without one, the same as before it was set
+    pub fn set_heartbeat_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if Socket::HEARTBEAT_HEADER_LEN + payload.len() > self.socket.max_message_size {
+            return Err(Error::MessageTooLong);
+        }
+
+        self.socket.heartbeat_payload = payload.to_vec();
+
+        Ok(())
+    }
+
+    /// sends the acks every receive reliable channel of a connection has queued straight away instead of on the next update
+    pub fn flush_acks(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        let Some(connection) = self.connections.get_mut(&addr) else {return Err(Error::AddressNotConnected);};
+
+        for channel in connection.channels.iter_mut() {
+            channel.flush_acks(&mut self.socket)?;
+        }
+
+        Ok(())
+    }
+
+    /// the state of a reliable or fec send channel's ring buffer of unacknowledged messages, for debugging stalls
+    ///
+    /// `None` if the address isn't connected, the channel doesn't exist or it isn't a reliable or fec send channel
+    pub fn debug_channel_state(&self, addr: SocketAddr, channel_id: u8) -> Option<ChannelDebug> {
+        self.connections.get(&addr)?.channels.get(channel_id as usize)?.debug_state()
+    }
+
+    /// how long until the soonest resend of a message on a reliable or fec send channel, from when it was last sent,
+    /// the connection's ping and the channel's current resend threshhold with backoff
+    ///
+    /// zero if a resend is overdue, like when the rate limit is holding it back,
+    /// `None` if the address isn't connected, the channel isn't a reliable or fec send channel,
+    /// no sent messages are waiting for acknowledgement or the ping hasn't been measured yet, as nothing is resent without it
+    pub fn next_retransmit(&self, addr: SocketAddr, channel_id: u8) -> Option<Duration> {
+        let connection = self.connections.get(&addr)?;
+
+        connection.channels.get(channel_id as usize)?.next_retransmit(connection.average_ping)
+    }
+
+    /// how long after being sent a message on a reliable or fec send channel is resent if it hasn't been acknowledged,
+    /// the connection's average ping times the channel's current resend threshhold
+    ///
+    /// each resend of the same message doubles it, see `Endpoint::next_retransmit` for when the next resend actually is
+    ///
+    /// `None` if the address isn't connected, the channel isn't a reliable or fec send channel or the ping hasn't been measured yet
+    pub fn resend_interval(&self, addr: SocketAddr, channel_id: u8) -> Option<Duration> {
+        let connection = self.connections.get(&addr)?;
+
+        connection.channels.get(channel_id as usize)?.resend_interval(connection.average_ping)
+    }
+
+    /// gets the estimated number of milliseconds the other client's system clock is ahead of this one's
+    ///
+    /// negative if it is behind, `None` if not connected or no estimate has been made yet
+    ///
+    /// the estimate uses the heartbeat with the lowest round trip time seen so far
+    pub fn clock_offset(&self, addr: SocketAddr) -> Option<i128> {
+        self.connections.get(&addr).and_then(|connection| connection.clock_offset)
+    }
+
+    /// returns true if the address is connected and has echoed back at least one heartbeat
+    pub fn is_reachable(&self, addr: SocketAddr) -> bool {
+        self.connections.get(&addr).is_some_and(|connection| connection.heartbeat_echoed)
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.connections.keys().cloned()
+    }
+
+    /// every connection with it's average ping in milliseconds, highest first, followed by any that haven't measured a ping yet
+    pub fn connections_by_ping(&self) -> Vec<(SocketAddr, Option<u128>)> {
+        let mut connections: Vec<_> = self.connections.iter().map(|(addr, connection)| (*addr, connection.average_ping)).collect();
+        connections.sort_by_key(|(_, ping)| std::cmp::Reverse(*ping));
+        connections
+    }
+
+    /// every connection with how many milliseconds ago something was last received from it, most recent first
+    pub fn connections_by_last_seen(&self) -> Vec<(SocketAddr, u128)> {
+        let mut connections: Vec<_> = self.connections.iter().map(|(addr, connection)| (*addr, connection.last_received_keep_alive.elapsed().as_millis())).collect();
+        connections.sort_by_key(|(_, last_seen)| *last_seen);
+        connections
+    }
+
+    /// sets a filter that decides whether to accept a connection from a new address when `ClientConfig::listen` is set,
+    /// replacing any previous filter
+    ///
+    /// rejected addresses are sent a disconnect packet, the same as when not listening
+    pub fn set_accept_filter(&mut self, accept_filter: impl FnMut(SocketAddr) -> bool + Send + 'static) {
+        self.accept_filter = Some(Box::new(accept_filter));
+    }
+
+    /// number of connections, including ones still handshaking
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// returns the diagnostic counters accumulated since the last call and resets them to zero
+    pub fn take_diagnostics(&mut self) -> Diagnostics {
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+
+        for channel in self.connections.values_mut().flat_map(|connection| connection.channels.iter_mut()) {
+            diagnostics.merge(&std::mem::take(&mut channel.diagnostics));
+        }
+
+        self.taken_diagnostics.merge(&diagnostics);
+
+        diagnostics
+    }
+
+    /// totals across the socket and every connection since the endpoint was created, for exporting to something like prometheus
+    ///
+    /// unlike `Endpoint::take_diagnostics` this doesn't reset anything, so it can be called by any number of exporters
+    pub fn metrics_snapshot(&self) -> Metrics {
+        let mut diagnostics = self.taken_diagnostics.clone();
+        diagnostics.merge(&self.diagnostics);
+
+        for channel in self.connections.values().flat_map(|connection| connection.channels.iter()) {
+            diagnostics.merge(&channel.diagnostics);
+        }
+
+        let traffic = &self.socket.traffic;
+
+        Metrics {
+            connections: self.connections.len(),
+            connections_total: self.connections_total,
+
+            packets_sent: traffic.packets_sent,
+            bytes_sent: traffic.bytes_sent,
+            packets_received: traffic.packets_received,
+            bytes_received: traffic.bytes_received,
+
+            retransmits: diagnostics.retransmits,
+            decode_failures: diagnostics.decode_failures,
+            invalid_packets: diagnostics.invalid_packets,
+            corrupt_packets: diagnostics.corrupt_packets,
+        }
+    }
+
+    /// the largest packet the endpoint will send or receive, `ClientConfig::max_message_size` less the checksum if there is one
+    pub fn mtu(&self) -> usize {
+        self.socket.max_message_size
+    }
+
+    /// the largest message that can be sent on a channel after headers are taken into account
+    pub fn max_payload(&self, channel_id: u8) -> Result<usize, Error> {
+        let Some(channel_config) = self.config.channels.get(channel_id as usize) else {return Err(Error::InvalidChannelId);};
+
+        channel_config.max_payload(self.socket.max_message_size).ok_or(Error::SendOnReceiveChannel)
+    }
+
+    pub fn bound_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr()
+    }
+
+    /// the port the socket is bound to, useful after `Endpoint::bind_any` to tell a rendezvous server where to reach this endpoint
+    pub fn local_port(&self) -> Result<u16, Error> {
+        Ok(self.bound_addr()?.port())
+    }
+
+    /// returns true if the socket is bound to the unspecified address (`0.0.0.0` or `::`), so it receives on every interface
+    ///
+    /// returns false if the bound address can't be read
+    pub fn is_wildcard_bound(&self) -> bool {
+        self.bound_addr().is_ok_and(|addr| addr.ip().is_unspecified())
+    }
+
+    /// sets the size of the os receive buffer (`SO_RCVBUF`) of the underlying socket
+    ///
+    /// the os may round or clamp the size, use `Endpoint::recv_buffer_size` to get the actual value
+    pub fn set_recv_buffer_size(&mut self, size: usize) -> Result<(), Error> {
+        Ok(socket2::SockRef::from(self.socket.udp()?).set_recv_buffer_size(size)?)
+    }
+
+    pub fn recv_buffer_size(&self) -> Result<usize, Error> {
+        Ok(socket2::SockRef::from(self.socket.udp()?).recv_buffer_size()?)
+    }
+
+    /// sets the size of the os send buffer (`SO_SNDBUF`) of the underlying socket
+    ///
+    /// the os may round or clamp the size, use `Endpoint::send_buffer_size` to get the actual value
+    pub fn set_send_buffer_size(&mut self, size: usize) -> Result<(), Error> {
+        Ok(socket2::SockRef::from(self.socket.udp()?).set_send_buffer_size(size)?)
+    }
+
+    pub fn send_buffer_size(&self) -> Result<usize, Error> {
+        Ok(socket2::SockRef::from(self.socket.udp()?).send_buffer_size()?)
+    }
+
+    /// gets a reference to the underlying socket for setting other socket options
+    ///
+    /// `socket2::SockRef::from` can be used on the reference for options not available on `UdpSocket`
+    ///
+    /// the socket shouldn't be read from, as any packets received that way will never reach the endpoint
+    ///
+    /// `None` for endpoints made with `Endpoint::loopback_pair`
+    pub fn socket_ref(&self) -> Option<&UdpSocket> {
+        self.socket.udp().ok()
+    }
+}
+
+fn system_time_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+
+/// small non cryptographic random number generator (splitmix64)
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: Option<u64>) -> Self {
+        Rng {
+            state: seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// generates a random instance id
+    fn instance(&mut self) -> [u8; 16] {
+        (((self.next_u64() as u128) << 64) | self.next_u64() as u128).to_be_bytes()
+    }
+}
+
+pub struct Connection<C = ()> {
+    addr: SocketAddr,
+
+    other_instance: Option<[u8; 16]>,
+
+    creation_time: Instant,
+    ping_memory: VecDeque<u128>,
+    average_ping: Option<u128>,
+    /// the moving average kept when `ClientConfig::ping_smoothing` is set, unrounded so small pings don't get stuck
+    smoothed_ping: Option<f64>,
+
+    /// if a heartbeat has been echoed back by the other client
+    heartbeat_echoed: bool,
+
+    /// lowest ping sample that included the other client's time
+    min_ping: Option<u128>,
+    /// how many milliseconds the other client's clock is ahead of ours
+    clock_offset: Option<i128>,
+
+    heartbeat_interval: u128,
+    timeout: u128,
+
+    last_received_keep_alive: Instant,
+    last_sent_keep_alive: Instant,
+
+    /// set once an `Event::ConnectionStalled` has been emitted, until something is received
+    stalled: bool,
+
+    channels: Vec<Channel>,
+    /// shares the send budget between channels, see `ClientConfig::fair_queuing`
+    fair_share: Option<FairShare>,
+
+    /// set while hole punching, until a heartbeat is echoed back
+    punch: Option<Punch>,
+    /// set while the `Event::Connection` is held back until a heartbeat is echoed, see `ClientConfig::require_handshake_ack`
+    unannounced: bool,
+
+    /// times of heartbeats sent with `Endpoint::send_ping` that haven't been echoed
+    pings: Vec<u128>,
+
+    /// channel ids and messages from `Endpoint::send_or_connect` waiting for a heartbeat to be echoed back,
+    /// or from `Endpoint::send` while the connection is paused
+    queued_sends: Vec<(u8, Vec<u8>)>,
+
+    /// when the connection was paused, see `Endpoint::pause_connection`
+    paused: Option<Instant>,
+
+    /// user data associated with the connection
+    context: C,
+}
+
+struct FairShare {
+    fair_queuing: FairQueuing,
+    last_refill: Instant,
+}
+
+impl FairShare {
+    /// hands out the budget since the last refill between the channels' grants by weight,
+    /// giving what would go over a full channel's burst to the others
+    fn refill(&mut self, channels: &mut [Channel]) {
+        let burst = self.fair_queuing.burst();
+
+        let mut refill = self.last_refill.elapsed().as_secs_f64() * self.fair_queuing.rate_limit as f64;
+        self.last_refill = Instant::now();
+
+        // each pass fills at least one channel or hands out everything
+        for _ in 0..channels.len() {
+            let total_weight: f64 = channels.iter()
+                .filter(|channel| channel.pacer.grant.is_some_and(|grant| grant < burst))
+                .map(|channel| self.fair_queuing.weight(channel.channel_id))
+                .sum();
+
+            if refill <= 0. || total_weight == 0. {break;}
+
+            let mut overflow = 0.;
+
+            for channel in channels.iter_mut() {
+                let Some(grant) = &mut channel.pacer.grant else {continue;};
+                if *grant >= burst {continue;}
+
+                *grant += refill * self.fair_queuing.weight(channel.channel_id) / total_weight;
+
+                overflow += (*grant - burst).max(0.);
+                *grant = grant.min(burst);
+            }
+
+            refill = overflow;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Punch {
+    attempts_left: u32,
+    interval: u128,
+    last_attempt: Instant,
+}
+
+impl<C> Connection<C> {
+    fn new(config: &ClientConfig, addr: SocketAddr, instance: &[u8; 16], socket: &mut Socket, context: C) -> Result<Self, Error> {
+        let creation_time = Instant::now();
+
+        socket.heartbeat(addr, instance, creation_time.elapsed().as_millis())?;
+
+        Ok(Connection {
+            addr,
+
+            other_instance: None,
+
+            creation_time,
+            ping_memory: VecDeque::new(),
+            smoothed_ping: None,
+            average_ping: None,
+
+            heartbeat_echoed: false,
+
+            min_ping: None,
+            clock_offset: None,
+
+            heartbeat_interval: config.heartbeat_interval,
+            timeout: config.timeout,
+
+            last_received_keep_alive: Instant::now(),
+            last_sent_keep_alive: Instant::now(),
+
+            stalled: false,
+
+            channels: config.channels.iter().enumerate().map(|(id, c)| Channel::new(c, id as u8, addr, config)).collect(),
+            fair_share: config.fair_queuing.clone().map(|fair_queuing| FairShare {
+                fair_queuing,
+                last_refill: Instant::now(),
+            }),
+
+            punch: None,
+            unannounced: false,
+
+            pings: Vec::new(),
+
+            queued_sends: Vec::new(),
+
+            paused: None,
+
+            context,
+        })
+    }
+
+    fn with_context<D>(self, context: D) -> Connection<D> {
+        Connection {
+            addr: self.addr,
+
+            other_instance: self.other_instance,
+
+            creation_time: self.creation_time,
+            ping_memory: self.ping_memory,
+            smoothed_ping: self.smoothed_ping,
+            average_ping: self.average_ping,
+
+            heartbeat_echoed: self.heartbeat_echoed,
+
+            min_ping: self.min_ping,
+            clock_offset: self.clock_offset,
+
+            heartbeat_interval: self.heartbeat_interval,
+            timeout: self.timeout,
+
+            last_received_keep_alive: self.last_received_keep_alive,
+            last_sent_keep_alive: self.last_sent_keep_alive,
+
+            stalled: self.stalled,
+
+            channels: self.channels,
+            fair_share: self.fair_share,
+
+            punch: self.punch,
+            unannounced: self.unannounced,
+
+            pings: self.pings,
+
+            queued_sends: self.queued_sends,
+
+            paused: self.paused,
+
+            context,
+        }
+    }
+
+    /// moves the connection to a new address, see `DuplicateInstance::Migrate`
+    fn migrate(&mut self, addr: SocketAddr) {
+        self.addr = addr;
+
+        for channel in self.channels.iter_mut() {
+            channel.addr = addr;
+        }
+    }
+
+    /// number of messages sent on reliable and fec channels that haven't been acknowledged
+    fn pending_messages(&self) -> usize {
+        self.channels.iter().map(Channel::pending_messages).sum()
+    }
+
+    /// takes acks queued by one of the receive reliable channels to piggyback on a message, see `ClientConfig::piggyback_acks`
+    fn take_ack(&mut self) -> Option<PiggybackedAck> {
+        self.channels.iter_mut().find_map(|channel| Some(PiggybackedAck {
+            channel_id: channel.channel_id,
+            ack: channel.take_ack()?,
+        }))
+    }
+
+    /// queues acks taken by `Connection::take_ack` again when they couldn't be sent
+    fn requeue_ack(&mut self, piggyback: PiggybackedAck) {
+        if let Some(channel) = self.channels.get_mut(piggyback.channel_id as usize) {
+            channel.requeue_ack(piggyback.ack);
+        }
+    }
+
+    /// sends a heartbeat, returning the time it carries
+    fn heartbeat(&mut self, instance: &[u8; 16], socket: &mut Socket) -> Result<u128, Error> {
+        let time = self.creation_time.elapsed().as_millis();
+
+        socket.heartbeat(self.addr, instance, time)?;
+        self.last_sent_keep_alive = Instant::now();
+
+        Ok(time)
+    }
+
+    /// sends every message held by `Endpoint::send_or_connect` or while paused, carrying on past any that fail
+    fn send_queued(&mut self, socket: &mut Socket, events: &mut Vec<Event>) {
+        for (channel_id, message) in std::mem::take(&mut self.queued_sends) {
+            let Some(channel) = self.channels.get_mut(channel_id as usize) else {
+                events.push(Event::SendFailed { addr: self.addr, channel_id, message, error: Error::InvalidChannelId });
+                continue;
+            };
+
+            match channel.send(&message, 0, socket) {
+                Ok(Some(seq)) => events.push(Event::QueuedSent(self.addr, MessageHandle { channel_id, seq })),
+                Ok(None) => {},
+                Err(error) => events.push(Event::SendFailed { addr: self.addr, channel_id, message, error }),
+            }
+
+            if channel.writable() {
+                events.push(Event::ChannelWritable(self.addr, channel_id));
+            }
+        }
+    }
+
+    fn update(&mut self, instance: &[u8; 16], socket: &mut Socket) -> Result<(), Error> {
+        if self.heartbeat_interval > 0 && self.last_sent_keep_alive.elapsed().as_millis() > self.heartbeat_interval {
+            self.heartbeat(instance, socket)?;
+        }
+
+        if let Some(fair_share) = &mut self.fair_share {
+            fair_share.refill(&mut self.channels);
+        }
+
+        for channel in self.channels.iter_mut() {
+            channel.update(self.average_ping, socket)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+pub enum Event {
+    /// always followed by an `Event::ChannelsReady` for the same address
+    Connection(SocketAddr),
+    /// the largest message that can be sent on each of a new connection's channels by channel id, `None` for receive channels,
+    /// given straight after it's `Event::Connection` so buffers can be sized once, see `Endpoint::max_payload`
+    ChannelsReady {
+        addr: SocketAddr,
+        max_payloads: Vec<Option<usize>>,
+    },
+    Disconnection(SocketAddr, DisconnectReason),
+    Message(SocketAddr, u8, Vec<u8>),
+    /// a message on a reliable, fec or unreliable sequenced channel with the sequence number it was sent with,
+    /// given instead of `Event::Message` when `ClientConfig::sequenced_messages` is set
+    SequencedMessage {
+        addr: SocketAddr,
+        channel_id: u8,
+        seq: u64,
+        message: Vec<u8>,
+        /// true if a fec message had symbols lost and needed repair symbols to decode, always false on reliable channels
+        repaired: bool,
+    },
+    /// a message sent on a reliable or fec channel has been acknowledged
+    ///
+    /// for fec channels this is once the whole message has been decoded
+    Delivered(SocketAddr, MessageHandle),
+    /// a reliable channel has dropped under `ClientConfig::low_water_mark` messages waiting for acknowledgement
+    ChannelWritable(SocketAddr, u8),
+    /// the first ping measurement of a connection has been made, with the ping in milliseconds
+    PingEstablished(SocketAddr, u128),
+    /// a ping sent with `Endpoint::send_ping` was echoed back, with the round trip time in milliseconds
+    Pong(SocketAddr, u128),
+    /// a packet sent with `Endpoint::send_raw` arrived, from any address unless `ClientConfig::strict_peers` is set
+    Raw(SocketAddr, Vec<u8>),
+    /// a packet sent with `Endpoint::send_broadcast` or `Endpoint::send_discovery` arrived, with it's payload
+    ///
+    /// like `Event::Raw` it comes from any address unless `ClientConfig::strict_peers` is set
+    Discovery(SocketAddr, Vec<u8>),
+    /// a heartbeat arrived with a payload, see `Endpoint::set_heartbeat_payload`
+    HeartbeatPayload(SocketAddr, Vec<u8>),
+    /// a packet was dropped for being malformed, only emitted if `ClientConfig::report_invalid_packets` is set
+    InvalidPacket {
+        from: SocketAddr,
+        reason: InvalidReason,
+    },
+    /// a message on a receive reliable channel arrived after sequences that haven't been received yet,
+    /// only emitted if `ClientConfig::report_gaps` is set
+    ///
+    /// every sequence skipped over by one message is in a single event, however far ahead it jumped
+    Gap {
+        addr: SocketAddr,
+        channel_id: u8,
+        missing_seqs: Range<u64>,
+    },
+    /// a sequence from an `Event::Gap` has been received, the message follows in it's own event
+    GapFilled {
+        addr: SocketAddr,
+        channel_id: u8,
+        seq: u64,
+    },
+    /// a chunk of a stream sent with `Endpoint::send_stream` has arrived on a `ChannelConfig::ReceiveReliableStream` channel
+    StreamProgress {
+        addr: SocketAddr,
+        channel_id: u8,
+        stream_id: u32,
+        received_chunks: u32,
+        total_chunks: u32,
+    },
+    /// every chunk of a stream has arrived, with the chunks joined back together
+    Stream {
+        addr: SocketAddr,
+        channel_id: u8,
+        stream_id: u32,
+        data: Vec<u8>,
+    },
+    /// a request sent with `Endpoint::request` has arrived on a `ChannelConfig::ReceiveReliableRequests` channel,
+    /// answer it by passing the id to `Endpoint::respond`
+    Request {
+        channel_id: u8,
+        request_id: RequestId,
+        message: Vec<u8>,
+    },
+    /// the response to a request sent with `Endpoint::request`, with the id it returned
+    Response(RequestId, Vec<u8>),
+    /// a connection has moved from one address to another, see `DuplicateInstance::Migrate`
+    ConnectionMigrated {
+        from: SocketAddr,
+        to: SocketAddr,
+    },
+    /// nothing has been received from a connection for `ClientConfig::stall_fraction` of it's timeout
+    ConnectionStalled(SocketAddr),
+    /// a stalled connection has received something again
+    ConnectionRecovered(SocketAddr),
+    /// a message queued by `Endpoint::send_or_connect` was dropped because the connection was lost before it could be sent
+    Unsent {
+        addr: SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    },
+    /// a message held by `Endpoint::send_or_connect` or while paused has been sent on a reliable or fec channel,
+    /// with the handle it's `Event::Delivered` will have
+    QueuedSent(SocketAddr, MessageHandle),
+    /// a message held by `Endpoint::send_or_connect` or while paused couldn't be sent once the connection was usable
+    SendFailed {
+        addr: SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+        error: Error,
+    },
+}
+
+impl Event {
+    fn channels_ready(addr: SocketAddr, channels: &[ChannelConfig], max_message_size: usize) -> Self {
+        Event::ChannelsReady {
+            addr,
+            max_payloads: channels.iter().map(|channel| channel.max_payload(max_message_size)).collect(),
+        }
+    }
+
+    /// the event for a message received on a channel, an `Event::SequencedMessage` if it has a sequence and `ClientConfig::sequenced_messages` is set
+    fn received(addr: SocketAddr, channel_id: u8, (seq, message, repaired): ReceivedMessage, sequenced_messages: bool) -> Self {
+        match seq {
+            Some(seq) if sequenced_messages => Event::SequencedMessage { addr, channel_id, seq, message, repaired },
+            _ => Event::Message(addr, channel_id, message),
+        }
+    }
+
+    /// the address of the connection the event is for
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Event::Connection(addr) => *addr,
+            Event::ChannelsReady { addr, .. } => *addr,
+            Event::Disconnection(addr, _) => *addr,
+            Event::Message(addr, _, _) => *addr,
+            Event::SequencedMessage { addr, .. } => *addr,
+            Event::Delivered(addr, _) => *addr,
+            Event::ChannelWritable(addr, _) => *addr,
+            Event::PingEstablished(addr, _) => *addr,
+            Event::Pong(addr, _) => *addr,
+            Event::HeartbeatPayload(addr, _) => *addr,
+            Event::Raw(addr, _) => *addr,
+            Event::Discovery(addr, _) => *addr,
+            Event::InvalidPacket { from, .. } => *from,
+            Event::Gap { addr, .. } => *addr,
+            Event::GapFilled { addr, .. } => *addr,
+            Event::StreamProgress { addr, .. } => *addr,
+            Event::Stream { addr, .. } => *addr,
+            Event::Request { request_id, .. } => request_id.addr,
+            Event::Response(request_id, _) => request_id.addr,
+            Event::ConnectionMigrated { to, .. } => *to,
+            Event::ConnectionStalled(addr) => *addr,
+            Event::ConnectionRecovered(addr) => *addr,
+            Event::Unsent { addr, .. } => *addr,
+            Event::QueuedSent(addr, _) => *addr,
+            Event::SendFailed { addr, .. } => *addr,
+        }
+    }
+
+    /// gets the contents of a message event as a string
+    ///
+    /// returns `None` if the event isn't a message, or `Error::InvalidUtf8` if the message isn't valid utf-8
+    pub fn message_str(&self) -> Option<Result<&str, Error>> {
+        let (Event::Message(_, _, message) | Event::SequencedMessage { message, .. }) = self else {return None;};
+
+        Some(std::str::from_utf8(message).map_err(Error::InvalidUtf8))
+    }
+
+    /// takes the contents of a message event as a string
+    ///
+    /// returns `None` if the event isn't a message, or `Error::InvalidUtf8` if the message isn't valid utf-8
+    pub fn into_message_string(self) -> Option<Result<String, Error>> {
+        let (Event::Message(_, _, message) | Event::SequencedMessage { message, .. }) = self else {return None;};
+
+        Some(String::from_utf8(message).map_err(|err| Error::InvalidUtf8(err.utf8_error())))
+    }
+
+    /// true for `Event::Connection` and `Event::Disconnection`
+    pub fn is_connection_change(&self) -> bool {
+        matches!(self, Event::Connection(_) | Event::Disconnection(_, _))
+    }
+
+    /// true for events carrying received data, `Event::Message`, `Event::SequencedMessage` and `Event::Stream`
+    pub fn is_message(&self) -> bool {
+        matches!(self, Event::Message(..) | Event::SequencedMessage { .. } | Event::Stream { .. })
+    }
+}
+
+/// splits the events from an update into connections and disconnections, messages, and everything else,
+/// keeping the order of each
+///
+/// see `Event::is_connection_change` and `Event::is_message`
+pub fn partition_events(events: Vec<Event>) -> (Vec<Event>, Vec<Event>, Vec<Event>) {
+    let mut connection_changes = Vec::new();
+    let mut messages = Vec::new();
+    let mut other = Vec::new();
+
+    for event in events {
+        if event.is_connection_change() {
+            connection_changes.push(event);
+        } else if event.is_message() {
+            messages.push(event);
+        } else {
+            other.push(event);
+        }
+    }
+
+    (connection_changes, messages, other)
+}
+
+/// identifies a message sent on a reliable or fec channel of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageHandle {
+    channel_id: u8,
+    seq: u64,
+}
+
+impl MessageHandle {
+    pub fn channel_id(&self) -> u8 {
+        self.channel_id
+    }
+}
+
+/// identifies a request from `Endpoint::request`, matching it to it's `Event::Response`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId {
+    addr: SocketAddr,
+    id: u64,
+}
+
+impl RequestId {
+    /// the address the request was sent to, or came from for an `Event::Request`
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidReason {
+    /// the packet had no bytes
+    Empty,
+    /// the packet was too short for it's header
+    Truncated,
+    /// the packet was for a channel id that isn't configured, likely because of mismatched channel configs
+    UnknownChannel,
+    /// the packet was for a channel that's unreliable and send only here, so the peer must have configured it to send as well
+    ///
+    /// reliable and fec send channels receive acks, so a mismatch there can't be told apart from a malformed ack
+    DirectionMismatch,
+    /// the packet's checksum didn't match, only checked when `ClientConfig::checksum` is set
+    ChecksumMismatch,
+}
+
+#[derive(Debug)]
+pub enum DisconnectReason {
+    Kicked,
+    Other,
+    Timeout,
+    /// the connection never echoed back a heartbeat within the handshake timeout
+    HandshakeTimeout,
+    OriginChangedInstance,
+    /// the connection's instance id is already connected from another address, see `DuplicateInstance::Reject`
+    DuplicateInstance,
+    /// a reliable or fec message needed resending more than the channel's max retransmits
+    ReliableFailure,
+    /// removed with `Endpoint::drop_connection`, without telling the peer
+    Dropped,
+    /// another connection migrated to the address, see `DuplicateInstance::Migrate`
+    Replaced,
+    /// every hole punching heartbeat went unechoed, see `Endpoint::punch`
+    PunchFailed,
+    /// a packet to the connection was rejected because nothing is bound to it's port, likely because the peer has crashed
+    ///
+    /// only detected on linux, other platforms either don't report it or don't say which address it was for,
+    /// so the connection times out instead
+    PortUnreachable,
+}
+
+
+/// statistics for a single channel of a connection
+#[derive(Debug, Clone, Default)]
+pub struct ChannelStats {
+    /// number of messages received on a reliable channel that had already been received,
+    /// which happens when an ack is lost or late and the message is resent
+    pub duplicates_received: u64,
+
+    /// the current resend threshhold of a reliable or fec channel, which changes if it's adaptive
+    pub resend_threshhold: Option<f32>,
+}
+
+/// a snapshot of a reliable or fec send channel, see `Endpoint::debug_channel_state`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelDebug {
+    /// the oldest sequence the channel is tracking, every sequence before it has been acknowledged
+    pub start_seq: u64,
+    /// the sequence the next message sent will get
+    pub next_seq: u64,
+    /// number of messages sent but not yet acknowledged
+    pub pending: usize,
+    /// the sequences of every message sent but not yet acknowledged, in order
+    pub outstanding: Vec<u64>,
+}
+
+/// counters across every connection of an endpoint, see `Endpoint::take_diagnostics`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// packets dropped for being malformed, whether or not `ClientConfig::report_invalid_packets` is set
+    pub invalid_packets: u64,
+    /// fec symbols that didn't agree with the rest of their message, and fec messages that failed to decode
+    pub decode_failures: u64,
+    /// reliable and fec messages resent because their ack didn't arrive in time
+    pub retransmits: u64,
+    /// fec messages that had source symbols lost and were decoded with repair symbols
+    pub fec_repaired: u64,
+    /// packets dropped for not matching their checksum, see `ClientConfig::checksum`
+    pub corrupt_packets: u64,
+}
+
+/// a snapshot of an endpoint's totals, see `Endpoint::metrics_snapshot`
+///
+/// counters only ever go up for the life of the endpoint, gauges are the value at the time of the snapshot
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// gauge, connections right now, including ones still handshaking
+    pub connections: usize,
+    /// counter, connections announced with `Event::Connection`
+    pub connections_total: u64,
+
+    /// counter, packets handed to the socket, including heartbeats, acks and retransmits
+    pub packets_sent: u64,
+    /// counter, bytes in the packets sent, including the checksum but not the udp or ip headers
+    pub bytes_sent: u64,
+    /// counter, packets received from any address, including ones that were dropped
+    pub packets_received: u64,
+    /// counter, bytes in the packets received, including the checksum but not the udp or ip headers
+    pub bytes_received: u64,
+
+    /// counter, see `Diagnostics::retransmits`
+    pub retransmits: u64,
+    /// counter, see `Diagnostics::decode_failures`
+    pub decode_failures: u64,
+    /// counter, see `Diagnostics::invalid_packets`
+    pub invalid_packets: u64,
+    /// counter, see `Diagnostics::corrupt_packets`
+    pub corrupt_packets: u64,
+}
+
+impl Diagnostics {
+    fn merge(&mut self, other: &Diagnostics) {
+        self.invalid_packets += other.invalid_packets;
+        self.decode_failures += other.decode_failures;
+        self.retransmits += other.retransmits;
+        self.fec_repaired += other.fec_repaired;
+        self.corrupt_packets += other.corrupt_packets;
+    }
+}
+
+
+enum GapChange {
+    Missing(Range<u64>),
+    Filled(u64),
+}
+
+enum StreamChange {
+    Progress {
+        stream_id: u32,
+        received_chunks: u32,
+        total_chunks: u32,
+    },
+    Complete {
+        stream_id: u32,
+        data: Vec<u8>,
+    },
+}
+
+struct Channel {
+    addr: SocketAddr,
+    channel_id: u8,
+
+    stats: ChannelStats,
+    diagnostics: Diagnostics,
+
+    /// set when a message needs resending more than the channel's max retransmits
+    failed: bool,
+
+    /// see `ClientConfig::low_water_mark`
+    low_water_mark: Option<usize>,
+    /// set while a send reliable channel has at least `low_water_mark` messages waiting for acknowledgement
+    above_low_water: bool,
+
+    /// sequence numbers of sent messages that have been acknowledged since the last call to `Channel::receive`
+    delivered: Vec<u64>,
+    /// sequences skipped over or filled in on a receive reliable channel since the last call to `Channel::receive`
+    gaps: Vec<GapChange>,
+    /// chunks and whole streams received on a receive reliable stream channel since the last call to `Channel::receive`
+    streams: Vec<StreamChange>,
+    /// requests and responses received on a receive reliable requests channel since the last call to `Channel::receive`
+    requests: Vec<(RequestHeader, Vec<u8>)>,
+
+    /// acks from a receive reliable channel to send with the next message, see `ClientConfig::piggyback_acks`
+    piggyback: Option<PiggybackedAck>,
+    /// acks for other channels that arrived with messages since the last call to `Channel::receive`
+    received_acks: Vec<PiggybackedAck>,
+
+    pacer: Pacer,
+
+    channel_type: ChannelType,
+}
+
+/// token bucket enforcing the rate limit of a send channel
+///
+/// the budget can go negative when a packet is larger than what's left, so any size of message can be sent
+struct Pacer {
+    /// bytes per second, `None` for no limit
+    rate_limit: Option<u64>,
+    /// bytes that can be sent before sending is deferred
+    budget: f64,
+    last_refill: Instant,
+
+    /// the channel's share of the connection's budget, `None` unless `ClientConfig::fair_queuing` is set
+    ///
+    /// topped up by `FairShare::refill`
+    grant: Option<f64>,
+}
+
+impl Pacer {
+    fn new(rate_limit: Option<u64>, grant: Option<f64>) -> Self {
+        Pacer {
+            rate_limit,
+            budget: rate_limit.map_or(0., |rate_limit| rate_limit as f64 / 10.),
+            last_refill: Instant::now(),
+
+            grant,
+        }
+    }
+
+    /// returns true if there is budget to send
+    fn ready(&mut self) -> bool {
+        if self.grant.is_some_and(|grant| grant <= 0.) {
+            return false;
+        }
+
+        let Some(rate_limit) = self.rate_limit else {return true;};
+
+        // the budget is capped at a tenth of a second's worth to keep bursts small
+        let refill = self.last_refill.elapsed().as_secs_f64() * rate_limit as f64;
+        self.budget = (self.budget + refill).min(rate_limit as f64 / 10.);
+        self.last_refill = Instant::now();
+
+        self.budget > 0.
+    }
+
+    fn spend(&mut self, bytes: usize) {
+        if let Some(grant) = &mut self.grant {
+            *grant -= bytes as f64;
+        }
+
+        if self.rate_limit.is_some() {
+            self.budget -= bytes as f64;
+        }
+    }
+}
+
+enum ChannelType {
+    SendUnreliable {
+        queue_limit: usize,
+        /// messages and when they expire, see `Endpoint::send_with_ttl`
+        queue: VecDeque<(Vec<u8>, Option<Instant>)>,
+    },
+    ReceiveUnreliable,
+
+    SendUnreliableBatched,
+    ReceiveUnreliableBatched,
+
+    SendUnreliableSequenced {
+        seq_counter: u64,
+    },
+    ReceiveUnreliableSequenced {
+        jitter_buffer: Option<u128>,
+        /// messages with a lower sequence are dropped
+        next_seq: u64,
+        /// messages held by the jitter buffer, with when they arrived
+        held: BTreeMap<u64, (Instant, Vec<u8>)>,
+    },
+
+    SendReliable {
+        resend_threshhold: f32,
+        adaptive_resend: Option<AdaptiveResend>,
+        max_retransmits: Option<u32>,
+        lifetime: Option<u128>,
+        /// set once a message has expired unacknowledged, after which every packet carries a skip
+        abandoned: bool,
+
+        seq_counter: u64,
+        /// id of the next stream sent with `Endpoint::send_stream`
+        stream_counter: u32,
+
+        messages_start_seq: u64,
+        messages: VecDeque<Option<SentMessage>>
+    },
+    ReceiveReliable {
+        acks_to_send: Vec<u64>,
+        ack_delay: Option<u128>,
+        /// when the oldest ack in `acks_to_send` was queued
+        oldest_ack: Option<Instant>,
+
+        report_gaps: bool,
+
+        /// partly received fragmented messages by the sequence of their first fragment
+        fragments: HashMap<u64, PartialStream>,
+        /// partly received streams by id, `None` unless the channel is a `ChannelConfig::ReceiveReliableStream`
+        streams: Option<HashMap<u32, PartialStream>>,
+        /// set for a `ChannelConfig::ReceiveReliableRequests`, where every message starts with a `RequestHeader`
+        requests: bool,
+
+        received_start_seq: u64,
+        received: VecDeque<bool>,
+    },
+
+    SendFecReliable {
+        resend_threshhold: f32,
+        adaptive_resend: Option<AdaptiveResend>,
+        max_retransmits: Option<u32>,
+
+        max_data_symbols: usize,
+        max_repair_symbols: usize,
+
+        seq_counter: u64,
+
+        messages_start_seq: u64,
+        messages: VecDeque<Option<SentFecMessage>>,
+    },
+    ReceiveFecReliable {
+        ordered: bool,
+
+        messages_start_seq: u64,
+        messages: VecDeque<ReceiveFecMessage>,
+    },
+}
+
+/// a reliable message that is waiting for acknowledgement
+struct SentMessage {
+    /// `None` while the first send is deferred by the channel's rate limit
+    last_sent: Option<Instant>,
+    retransmits: u32,
+    /// higher priority messages get retransmitted first
+    priority: u8,
+    fragment: Option<Fragment>,
+    message: Vec<u8>,
+    /// when the message stops being resent, the same for every fragment of a message, see `ChannelConfig::SendReliable::lifetime`
+    expires: Option<Instant>,
+}
+
+/// a fec message that is waiting for acknowledgement
+struct SentFecMessage {
+    /// `None` while the first send is deferred by the channel's rate limit
+    last_sent: Option<Instant>,
+    retransmits: u32,
+    /// higher priority messages get retransmitted first
+    priority: u8,
+    /// the encoded packets of the message, `None` for packets that have been acknowledged
+    packets: Vec<Option<Vec<u8>>>,
+}
+
+/// a stream or fragmented message that's still missing chunks
+struct PartialStream {
+    chunk_count: u32,
+    chunks: HashMap<u32, Vec<u8>>,
+}
+
+enum ReceiveFecMessage {
+    NotSeen,
+    Receiving {
+        decoder: raptor_code::SourceBlockDecoder,
+
+        // taken from the first symbol received, later symbols that disagree are ignored
+        num_source_symbols: u32,
+        message_len: u16,
+        symbol_len: usize,
+
+        /// which source symbols have arrived, a message decoded without all of them needed repair symbols
+        source_symbols_received: Vec<bool>,
+    },
+    /// decoded on an ordered channel but waiting for earlier messages, with whether it was repaired
+    Held(Vec<u8>, bool),
+    Received,
+}
+
+/// a received message, it's sequence number on reliable and fec channels, and whether fec had to repair it
+type ReceivedMessage = (Option<u64>, Vec<u8>, bool);
+
+impl Channel {
+    fn new(config: &ChannelConfig, channel_id: u8, addr: SocketAddr, client_config: &ClientConfig) -> Self {
+        Channel {
+            addr,
+            channel_id,
+
+            stats: ChannelStats::default(),
+            diagnostics: Diagnostics::default(),
+
+            failed: false,
+
+            low_water_mark: client_config.low_water_mark,
+            above_low_water: false,
+
+            delivered: Vec::new(),
+            gaps: Vec::new(),
+            streams: Vec::new(),
+            requests: Vec::new(),
+
+            piggyback: None,
+            received_acks: Vec::new(),
+
+            pacer: Pacer::new(config.rate_limit(), client_config.fair_queuing.as_ref().filter(|_| config.is_paced()).map(|fair_queuing| {
+                // start with the channel's share of a burst, counting the channel in case it's being added
+                let total_weight = fair_queuing.total_weight(&client_config.channels).max(fair_queuing.weight(channel_id));
+                fair_queuing.burst() * fair_queuing.weight(channel_id) / total_weight
+            })),
+
+            channel_type: match config {
+                ChannelConfig::SendUnreliable { queue_limit, .. } => ChannelType::SendUnreliable {
+                    queue_limit: *queue_limit,
+                    queue: VecDeque::new(),
+                },
+                ChannelConfig::ReceiveUnreliable => ChannelType::ReceiveUnreliable,
+
+                ChannelConfig::SendUnreliableBatched => ChannelType::SendUnreliableBatched,
+                ChannelConfig::ReceiveUnreliableBatched => ChannelType::ReceiveUnreliableBatched,
+                ChannelConfig::SendUnreliableSequenced => ChannelType::SendUnreliableSequenced {
+                    seq_counter: 0,
+                },
+                ChannelConfig::ReceiveUnreliableSequenced { jitter_buffer } => ChannelType::ReceiveUnreliableSequenced {
+                    jitter_buffer: *jitter_buffer,
+                    next_seq: 0,
+                    held: BTreeMap::new(),
+                },
+
+                ChannelConfig::SendReliable { resend_threshhold, adaptive_resend, max_retransmits, lifetime, .. } => ChannelType::SendReliable {
+                    resend_threshhold: *resend_threshhold,
+                    adaptive_resend: *adaptive_resend,
+                    max_retransmits: *max_retransmits,
+                    lifetime: *lifetime,
+                    abandoned: false,
+
+                    seq_counter: 0,
+                    stream_counter: 0,
+
+                    messages_start_seq: 0,
+                    messages: VecDeque::new(),
+                },
+                ChannelConfig::ReceiveReliable => ChannelType::ReceiveReliable {
+                    acks_to_send: Vec::new(),
+                    ack_delay: client_config.ack_delay,
+                    oldest_ack: None,
+
+                    report_gaps: client_config.report_gaps,
+
+                    fragments: HashMap::new(),
+                    streams: None,
+                    requests: false,
+
+                    received_start_seq: 0,
+                    received: VecDeque::new(),
+                },
+                ChannelConfig::ReceiveReliableStream => ChannelType::ReceiveReliable {
+                    acks_to_send: Vec::new(),
+                    ack_delay: client_config.ack_delay,
+                    oldest_ack: None,
+
+                    report_gaps: client_config.report_gaps,
+
+                    fragments: HashMap::new(),
+                    streams: Some(HashMap::new()),
+                    requests: false,
+
+                    received_start_seq: 0,
+                    received: VecDeque::new(),
+                },
+                ChannelConfig::ReceiveReliableRequests => ChannelType::ReceiveReliable {
+                    acks_to_send: Vec::new(),
+                    ack_delay: client_config.ack_delay,
+                    oldest_ack: None,
+
+                    report_gaps: client_config.report_gaps,
+
+                    fragments: HashMap::new(),
+                    streams: None,
+                    requests: true,
+
+                    received_start_seq: 0,
+                    received: VecDeque::new(),
+                },
+
+                ChannelConfig::SendFecReliable { resend_threshhold, adaptive_resend, max_retransmits, max_data_symbols, max_repair_symbols, .. } => ChannelType::SendFecReliable {
+                    resend_threshhold: *resend_threshhold,
+                    adaptive_resend: *adaptive_resend,
+                    max_retransmits: *max_retransmits,
+
+                    max_data_symbols: *max_data_symbols,
+                    max_repair_symbols: *max_repair_symbols,
+
+                    seq_counter: 0,
+
+                    messages_start_seq: 0,
+                    messages: VecDeque::new(),
+                },
+                ChannelConfig::ReceiveFecReliable => ChannelType::ReceiveFecReliable {
+                    ordered: false,
+
+                    messages_start_seq: 0,
+                    messages: VecDeque::new(),
+                },
+                ChannelConfig::ReceiveFecReliableOrdered => ChannelType::ReceiveFecReliable {
+                    ordered: true,
+
+                    messages_start_seq: 0,
+                    messages: VecDeque::new(),
+                },
+            }
+        }
+    }
+
+    /// sends a message, returning it's sequence number on reliable and fec channels
+    fn send(&mut self, message: &[u8], priority: u8, socket: &mut Socket) -> Result<Option<u64>, Error> {
+        socket.channel_prefix(self.channel_id)?;
+
+        Ok(match &mut self.channel_type {
+            ChannelType::ReceiveUnreliable => return Err(Error::SendOnReceiveChannel),
+            ChannelType::ReceiveUnreliableBatched => return Err(Error::SendOnReceiveChannel),
+            ChannelType::ReceiveUnreliableSequenced { .. } => return Err(Error::SendOnReceiveChannel),
+            ChannelType::ReceiveReliable { .. } => return Err(Error::SendOnReceiveChannel),
+            ChannelType::ReceiveFecReliable { .. } => return Err(Error::SendOnReceiveChannel),
+
+
+            ChannelType::SendUnreliable { .. } => {
+                self.queue_unreliable(message, None, socket)?;
+
+                None
+            },
+
+
+            ChannelType::SendUnreliableBatched => {
+                self.send_batch(&[message], socket)?;
+
+                None
+            },
+
+
+            ChannelType::SendUnreliableSequenced { seq_counter } => {
+                if message.len() > socket.max_message_size.saturating_sub(1 + SEQUENCED_PREFIX_LEN) {
+                    return Err(Error::MessageTooLong);
+                }
+
+                let seq = *seq_counter;
+                *seq_counter = seq.checked_add(1).ok_or(Error::SequencesExhausted)?;
+
+                socket.write(&seq.to_be_bytes())?;
+                socket.write(message)?;
+                socket.send(self.addr)?;
+
+                None
+            },
+
+
+            ChannelType::SendReliable { seq_counter, messages, messages_start_seq, lifetime, abandoned, .. } => {
+                // room is kept for a skip once messages start expiring
+                let skip_len = if lifetime.is_some() {ReliableHeader::SKIP_LEN} else {0};
+
+                // messages too long for a packet are split into fragments on consecutive sequences
+                let fragments: Vec<(Option<Fragment>, &[u8])> = match reliable_fragment_len(message.len(), socket.max_message_size, skip_len) {
+                    None => vec![(None, message)],
+                    Some(fragment_len) => {
+                        let chunks: Vec<&[u8]> = message.chunks(fragment_len).collect();
+                        let Ok(count) = u16::try_from(chunks.len()) else {return Err(Error::MessageTooLong);};
+
+                        chunks.into_iter().enumerate().map(|(index, chunk)| (Some(Fragment { index: index as u16, count }), chunk)).collect()
+                    },
+                };
+
+                if seq_counter.checked_add(fragments.len() as u64).is_none() {
+                    return Err(Error::SequencesExhausted);
+                }
+
+                let seq = *seq_counter;
+
+                // a lifetime too long to represent never expires
+                let expires = lifetime.and_then(|lifetime| Instant::now().checked_add(Duration::from_millis(lifetime.min(u64::MAX as u128) as u64)));
+
+                for (fragment, message) in fragments {
+                    // messages already deferred are sent first
+                    let deferred = messages.back().is_some_and(|message| message.as_ref().is_some_and(|message| message.last_sent.is_none()));
+
+                    let last_sent = if !deferred && self.pacer.ready() {
+                        let mut header = ReliableHeader { seq: *seq_counter, fragment, ack: None, skip: abandoned.then_some(*messages_start_seq) };
+
+                        // acks from a receive channel ride along if there's room, see `ClientConfig::piggyback_acks`
+                        if 1 + header.len() + PiggybackedAck::LEN + message.len() <= socket.max_message_size {
+                            header.ack = self.piggyback.take();
+                        }
+
+                        socket.channel_prefix(self.channel_id)?;
+                        socket.write(&header.encode())?;
+                        socket.write(message)?;
+                        let sent = socket.send(self.addr)?;
+                        self.pacer.spend(sent);
+
+                        Some(Instant::now())
+                    } else {
+                        None
+                    };
+
+                    messages.push_back(Some(SentMessage {
+                        last_sent,
+                        retransmits: 0,
+                        priority,
+                        fragment,
+                        message: Vec::from(message),
+                        expires,
+                    }));
+                    *seq_counter += 1;
+                }
+
+                Some(seq)
+            },
+
+
+            ChannelType::SendFecReliable { max_data_symbols, max_repair_symbols, seq_counter, messages, .. } => {
+                // the same limit as `ChannelConfig::max_payload`, longer messages would need symbols bigger than a packet
+                let max_symbol_len = socket.max_message_size.saturating_sub(1 + FecHeader::LEN);
+                if message.len() > max_symbol_len.saturating_mul(*max_data_symbols) {
+                    return Err(Error::MessageTooLong);
+                }
+
+                let message_len = u16::try_from(message.len()).map_err(|_| Error::MessageTooLong)?;
+
+                if *seq_counter == u64::MAX {
+                    return Err(Error::SequencesExhausted);
+                }
+
+                let deferred = messages.back().is_some_and(|message| message.as_ref().is_some_and(|message| message.last_sent.is_none()));
+                let send_now = !deferred && self.pacer.ready();
+
+                // the header carries the real length, so the receiver drops the padding when decoding
+                let mut source = message.to_vec();
+                source.resize(message.len().max(MIN_FEC_SOURCE_LEN), 0);
+
+                let (encoded_symbols, num_source_symbols) = raptor_code::encode_source_block(
+                    &source,
+                    *max_data_symbols,
+                    *max_repair_symbols,
+                );
+
+                // the symbol index is a single byte, the config is checked at bind but a truncated index would silently corrupt decoding
+                if encoded_symbols.len() > u8::MAX as usize {
+                    return Err(Error::InvalidFecConfig);
+                }
+
+                let mut packets = Vec::new();
+
+                for (encoded_symbol_index, encoded_symbol) in encoded_symbols.iter().enumerate() {
+                    let header = FecHeader {
+                        seq: *seq_counter,
+                        num_source_symbols,
+                        symbol_index: encoded_symbol_index as u8,
+                        message_len,
+                    };
+
+                    let mut packet = Vec::from(header.encode());
+                    packet.extend_from_slice(encoded_symbol);
+
+                    if send_now {
+                        socket.channel_prefix(self.channel_id)?;
+                        socket.write(&packet)?;
+                        socket.queue_datagram(self.addr);
+                        self.pacer.spend(packet.len() + 1);
+                    }
+
+                    packets.push(Some(packet));
+                }
+
+                socket.flush_datagrams()?;
+
+                messages.push_back(Some(SentFecMessage {
+                    last_sent: send_now.then(Instant::now),
+                    retransmits: 0,
+                    priority,
+                    packets,
+                }));
+                *seq_counter += 1;
+
+                Some(*seq_counter - 1)
+            },
+        })
+    }
+
+    /// handles a packet for the channel, returning any messages received along with their sequence number on reliable and fec channels
+    ///
+    /// sequence numbers of sent messages that get acknowledged are added to `Channel::delivered`
+    fn receive(&mut self, message: Vec<u8>, ping: Option<u128>, socket: &mut Socket) -> Result<Vec<ReceivedMessage>, Error> {
+        let received = match &mut self.channel_type {
+            ChannelType::SendUnreliable { .. } => vec![],
+
+            ChannelType::ReceiveUnreliable => vec![(None, message, false)],
+
+            ChannelType::SendUnreliableBatched => vec![],
+
+            ChannelType::ReceiveUnreliableBatched => wire::split_batched(&message).into_iter().map(|message| (None, Vec::from(message), false)).collect(),
+
+            ChannelType::SendUnreliableSequenced { .. } => vec![],
+
+            ChannelType::ReceiveUnreliableSequenced { jitter_buffer, next_seq, held } => 'b: {
+                let Some((seq, message)) = message.split_first_chunk::<SEQUENCED_PREFIX_LEN>() else {break 'b vec![];};
+                let seq = u64::from_be_bytes(*seq);
+
+                // too late, a later message has already been given
+                if seq < *next_seq {break 'b vec![];}
+
+                if jitter_buffer.is_none() {
+                    *next_seq = seq.saturating_add(1);
+                    break 'b vec![(Some(seq), Vec::from(message), false)];
+                }
+
+                held.insert(seq, (Instant::now(), Vec::from(message)));
+
+                self.release_held()
+            },
+
+            ChannelType::SendReliable { messages_start_seq, messages, resend_threshhold, adaptive_resend, .. } => 'b: {
+                let Some(ack) = ReliableAck::decode(&message) else {break 'b vec![];};
+
+                for seq in ack.acked() {
+                    if seq < *messages_start_seq {continue;}
+
+                    // will fail if seq hasn't been sent
+                    let Some(entry) = messages.get_mut((seq - *messages_start_seq) as usize) else {continue;};
+
+                    // mark entry as received
+                    if let Some(sent) = entry.take() {
+                        match sent.fragment {
+                            None => self.delivered.push(seq),
+                            // a fragmented message is delivered once every fragment has been acknowledged
+                            Some(Fragment { index, count }) => {
+                                let first_seq = seq - index as u64;
+                                let acked = (first_seq..first_seq + count as u64).all(|seq| seq < *messages_start_seq || messages.get((seq - *messages_start_seq) as usize).is_none_or(Option::is_none));
+
+                                if acked {
+                                    self.delivered.push(first_seq);
+                                }
+                            },
+                        }
+
+                        if let Some(adaptive_resend) = adaptive_resend {
+                            adaptive_resend.adapt(resend_threshhold, sent.retransmits, sent.last_sent, ping);
+                        }
+                    }
+                }
+
+                while let Some(None) = messages.front() {
+                    messages.pop_front();
+                    *messages_start_seq += 1;
+                }
+
+                vec![]
+            },
+
+            ChannelType::ReceiveReliable { acks_to_send, oldest_ack, report_gaps, fragments, streams, requests, received_start_seq, received, .. } => 'b: {
+                // only return messages with sequence numbers that haven't been seen
+
+                let Some((ReliableHeader { seq, fragment, ack, skip }, payload)) = ReliableHeader::decode(&message) else {break 'b vec![];};
+
+                if let Some(ack) = ack {
+                    self.received_acks.push(ack);
+                }
+
+                // the sender has given up on every sequence before the skip, so they're treated as seen
+                if let Some(skip) = skip.filter(|skip| *skip > *received_start_seq) {
+                    let skipped = (skip - *received_start_seq).min(received.len() as u64) as usize;
+                    received.drain(..skipped);
+                    *received_start_seq = skip;
+
+                    while let Some(true) = received.front() {
+                        received.pop_front();
+                        *received_start_seq += 1;
+                    }
+
+                    // fragmented messages missing a fragment that will never come
+                    fragments.retain(|first_seq, message| *first_seq >= skip || (0..message.chunk_count).all(|index| first_seq + index as u64 >= skip || message.chunks.contains_key(&index)));
+                }
+
+                // too far ahead to keep track of, it will be resent
+                if seq >= *received_start_seq && seq - *received_start_seq >= RECEIVE_WINDOW {
+                    break 'b vec![];
+                }
+
+                acks_to_send.push(seq);
+                oldest_ack.get_or_insert_with(Instant::now);
+
+                if seq < *received_start_seq {
+                    self.stats.duplicates_received += 1;
+                    break 'b vec![];
+                }
+
+                let i = (seq - *received_start_seq) as usize;
+
+                // every unseen sequence before the end of the ring buffer has already been reported as missing
+                if *report_gaps {
+                    let next_seq = *received_start_seq + received.len() as u64;
+
+                    if seq > next_seq {
+                        self.gaps.push(GapChange::Missing(next_seq..seq));
+                    } else if seq < next_seq && !received[i] {
+                        self.gaps.push(GapChange::Filled(seq));
+                    }
+                }
+
+                let seen = loop {
+                    match received.get_mut(i) {
+                        None => received.push_back(false),
+                        Some(e) => break e,
+                    }
+                };
+
+                if *seen {
+                    self.stats.duplicates_received += 1;
+                    break 'b vec![];
+                }
+
+                *seen = true;
+
+                while let Some(true) = received.front() {
+                    received.pop_front();
+                    *received_start_seq += 1;
+                }
+
+                // a fragmented message is given the sequence of it's first fragment, like the handle it was sent with
+                let (seq, payload) = match fragment {
+                    None => (seq, Vec::from(payload)),
+                    Some(Fragment { index, count }) => {
+                        let Some(first_seq) = seq.checked_sub(index as u64).filter(|_| index < count) else {
+                            self.diagnostics.invalid_packets += 1;
+                            break 'b vec![];
+                        };
+
+                        let message = fragments.entry(first_seq).or_insert_with(|| PartialStream {
+                            chunk_count: count as u32,
+                            chunks: HashMap::new(),
+                        });
+
+                        // every fragment of a message has to agree
+                        if message.chunk_count != count as u32 {
+                            self.diagnostics.invalid_packets += 1;
+                            break 'b vec![];
+                        }
+
+                        message.chunks.insert(index as u32, Vec::from(payload));
+
+                        if (message.chunks.len() as u32) < message.chunk_count {
+                            break 'b vec![];
+                        }
+
+                        let Some(mut message) = fragments.remove(&first_seq) else {break 'b vec![];};
+
+                        (first_seq, (0..count as u32).filter_map(|index| message.chunks.remove(&index)).flatten().collect())
+                    },
+                };
+
+                if *requests {
+                    let Some((header, payload)) = RequestHeader::decode(&payload) else {
+                        self.diagnostics.invalid_packets += 1;
+                        break 'b vec![];
+                    };
+
+                    self.requests.push((header, Vec::from(payload)));
+                    break 'b vec![];
+                }
+
+                let Some(streams) = streams else {break 'b vec![(Some(seq), payload, false)];};
+
+                let Some((StreamHeader { stream_id, chunk_index, chunk_count }, chunk)) = StreamHeader::decode(&payload) else {
+                    self.diagnostics.invalid_packets += 1;
+                    break 'b vec![];
+                };
+
+                if chunk_index >= chunk_count {
+                    self.diagnostics.invalid_packets += 1;
+                    break 'b vec![];
+                }
+
+                let stream = streams.entry(stream_id).or_insert_with(|| PartialStream {
+                    chunk_count,
+                    chunks: HashMap::new(),
+                });
+
+                // every chunk of a stream has to agree
+                if chunk_count != stream.chunk_count {
+                    self.diagnostics.invalid_packets += 1;
+                    break 'b vec![];
+                }
+
+                stream.chunks.insert(chunk_index, Vec::from(chunk));
+
+                self.streams.push(StreamChange::Progress {
+                    stream_id,
+                    received_chunks: stream.chunks.len() as u32,
+                    total_chunks: chunk_count,
+                });
+
+                if stream.chunks.len() as u32 == chunk_count {
+                    if let Some(mut stream) = streams.remove(&stream_id) {
+                        let data = (0..chunk_count).filter_map(|chunk_index| stream.chunks.remove(&chunk_index)).flatten().collect();
+
+                        self.streams.push(StreamChange::Complete { stream_id, data });
+                    }
+                }
+
+                vec![]
+            },
+
+            ChannelType::SendFecReliable { messages_start_seq, messages, resend_threshhold, adaptive_resend, .. } => {
+                match FecAck::decode(&message) {
+                    // whole message received acknowledgement
+                    Some(FecAck::Message { seq: seq_id }) => 'b: {
+                        if seq_id < *messages_start_seq {break 'b;}
+
+                        if let Some(message) = messages.get_mut((seq_id - *messages_start_seq) as usize) {
+                            // mark message as received
+                            if let Some(sent) = message.take() {
+                                self.delivered.push(seq_id);
+
+                                if let Some(adaptive_resend) = adaptive_resend {
+                                    adaptive_resend.adapt(resend_threshhold, sent.retransmits, sent.last_sent, ping);
+                                }
+                            }
+
+                            // clear front of message ring buffer
+                            while let Some(None) = messages.front() {
+                                messages.pop_front();
+                                *messages_start_seq += 1;
+                            }
+                        }
+                    },
+                    // single symbol/packet received acknowledgement
+                    Some(FecAck::Symbol { seq: seq_id, symbol_index }) => 'b: {
+                        if seq_id < *messages_start_seq {break 'b;}
+
+                        if let Some(message) = messages.get_mut((seq_id - *messages_start_seq) as usize) {
+                            if let Some(SentFecMessage { packets: symbols, retransmits, last_sent, .. }) = message {
+                                if let Some(symbol) = symbols.get_mut(symbol_index as usize) {
+                                    // mark packet/symbol as received
+                                    *symbol = None;
+
+                                    // mark as sent if every packet gets acknowledged
+                                    if !symbols.iter().any(|e| e.is_some()) {
+                                        self.delivered.push(seq_id);
+
+                                        if let Some(adaptive_resend) = adaptive_resend {
+                                            adaptive_resend.adapt(resend_threshhold, *retransmits, *last_sent, ping);
+                                        }
+
+                                        *message = None;
+
+                                        // clear front of message ring buffer
+                                        while let Some(None) = messages.front() {
+                                            messages.pop_front();
+                                            *messages_start_seq += 1;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    None => (),
+                }
+
+                vec![]
+            },
+
+            ChannelType::ReceiveFecReliable { ordered, messages, messages_start_seq } => 'b: {
+
+                let Some((header, symbol)) = FecHeader::decode(&message) else {break 'b vec![];};
+                let FecHeader { seq: seq_id, num_source_symbols, symbol_index, message_len: source_block_length } = header;
+
+                // a message can't have more source symbols than there are symbol indices, or more data than it's symbols hold
+                if num_source_symbols == 0 || num_source_symbols > u8::MAX as u32 + 1 || symbol.len() * (num_source_symbols as usize) < source_block_length as usize {
+                    self.diagnostics.decode_failures += 1;
+                    break 'b vec![];
+                }
+
+                // get the entry for the given seq_id in the receiving messages ring buffer
+                if seq_id < *messages_start_seq {
+                    // send ack for full message received
+                    socket.channel_prefix(self.channel_id)?;
+                    socket.write(&FecAck::Message { seq: seq_id }.encode())?;
+                    socket.send(self.addr)?;
+
+                    break 'b vec![];
+                }
+
+                // too far ahead to keep track of, it will be resent
+                if seq_id - *messages_start_seq >= RECEIVE_WINDOW {
+                    break 'b vec![];
+                }
+
+                let index = (seq_id - *messages_start_seq) as usize;
+
+                let receiving_message = loop {
+                    match messages.get_mut(index) {
+                        None => messages.push_back(ReceiveFecMessage::NotSeen),
+                        Some(message) => break message,
+                    }
+                };
+
+                // if not seen yet initialize the decoder
+                if let ReceiveFecMessage::NotSeen = receiving_message {
+                    *receiving_message = ReceiveFecMessage::Receiving {
+                        decoder: raptor_code::SourceBlockDecoder::new(num_source_symbols as usize,),
+                        num_source_symbols,
+                        message_len: source_block_length,
+                        symbol_len: symbol.len(),
+
+                        source_symbols_received: vec![false; num_source_symbols as usize],
+                    };
+                }
+
+                // get the decoder
+                let (decoder, source_symbols_received) = match receiving_message {
+                    ReceiveFecMessage::NotSeen => unreachable!(),
+                    ReceiveFecMessage::Held(..) | ReceiveFecMessage::Received => {
+                        // send ack for full message received
+                        socket.channel_prefix(self.channel_id)?;
+                        socket.write(&FecAck::Message { seq: seq_id }.encode())?;
+                        socket.send(self.addr)?;
+
+                        break 'b vec![];
+                    },
+                    ReceiveFecMessage::Receiving { decoder, num_source_symbols: expected_source_symbols, message_len, symbol_len, source_symbols_received } => {
+                        // every symbol of a message has to agree
+                        if num_source_symbols != *expected_source_symbols || source_block_length != *message_len || symbol.len() != *symbol_len {
+                            self.diagnostics.decode_failures += 1;
+                            break 'b vec![];
+                        }
+
+                        (decoder, source_symbols_received)
+                    },
+                };
+
+                // send ack for single symbol received
+                socket.channel_prefix(self.channel_id)?;
+                socket.write(&FecAck::Symbol { seq: seq_id, symbol_index }.encode())?;
+                socket.send(self.addr)?;
+
+                // push the symbol to the decoder
+                decoder.push_encoding_symbol(symbol, symbol_index as u32);
+
+                if let Some(received) = source_symbols_received.get_mut(symbol_index as usize) {
+                    *received = true;
+                }
+
+                // check if decoding is possible
+                if !decoder.fully_specified() {
+                    break 'b vec![];
+                }
+
+                let Some(message) = decoder.decode(source_block_length as usize) else {
+                    self.diagnostics.decode_failures += 1;
+                    break 'b vec![];
+                };
+
+                let repaired = !source_symbols_received.iter().all(|received| *received);
+
+                if repaired {
+                    self.diagnostics.fec_repaired += 1;
+                }
+
+                // send ack for full message received
+                socket.channel_prefix(self.channel_id)?;
+                socket.write(&FecAck::Message { seq: seq_id }.encode())?;
+                socket.send(self.addr)?;
+
+                let mut received = Vec::new();
+
+                if *ordered {
+                    *receiving_message = ReceiveFecMessage::Held(message, repaired);
+                } else {
+                    *receiving_message = ReceiveFecMessage::Received;
+                    received.push((Some(seq_id), message, repaired));
+                }
+
+                // clear the front of the receiving ring buffer, releasing held messages that are now in order
+                loop {
+                    match messages.front_mut() {
+                        Some(ReceiveFecMessage::Received) => (),
+                        Some(ReceiveFecMessage::Held(message, repaired)) => received.push((Some(*messages_start_seq), std::mem::take(message), *repaired)),
+                        _ => break,
+                    }
+
+                    messages.pop_front();
+                    *messages_start_seq += 1;
+                }
+
+                received
+            }
+        };
+
+        // send held acks straight away once they've waited long enough, rather than waiting for the next update
+        if let ChannelType::ReceiveReliable { ack_delay: Some(ack_delay), oldest_ack: Some(oldest_ack), .. } = &self.channel_type {
+            if oldest_ack.elapsed().as_millis() >= *ack_delay {
+                self.flush_acks(socket)?;
+            }
+        }
+
+        Ok(received)
+    }
+
+    /// splits data into as many messages as it takes on a send reliable channel, returning the id of the stream
+    fn send_stream(&mut self, data: &[u8], socket: &mut Socket) -> Result<u32, Error> {
+        let ChannelType::SendReliable { stream_counter, .. } = &mut self.channel_type else {return Err(Error::NotReliableChannel);};
+
+        let stream_id = *stream_counter;
+        *stream_counter = stream_counter.wrapping_add(1);
+
+        // chunks fit in a packet so they're never fragmented
+        let chunk_len = socket.max_message_size.saturating_sub(1 + ReliableHeader::MIN_LEN + StreamHeader::LEN).max(1);
+
+        // an empty stream is still sent as a single empty chunk so the peer sees it
+        let chunks: Vec<&[u8]> = if data.is_empty() {vec![&[]]} else {data.chunks(chunk_len).collect()};
+        let Ok(chunk_count) = u32::try_from(chunks.len()) else {return Err(Error::MessageTooLong);};
 
-    creation_time: Instant,
-    ping_memory: VecDeque<u128>,
-    average_ping: Option<u128>,
+        for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+            let mut message = Vec::with_capacity(StreamHeader::LEN + chunk.len());
+            message.extend_from_slice(&StreamHeader { stream_id, chunk_index: chunk_index as u32, chunk_count }.encode());
+            message.extend_from_slice(chunk);
 
-    heartbeat_interval: u128,
+            self.send(&message, 0, socket)?;
+        }
 
-    last_received_keep_alive: Instant,
-    last_sent_keep_alive: Instant,
+        Ok(stream_id)
+    }
 
-    channels: Vec<Channel>,
-}
+    /// packs messages into as few packets as possible on a send unreliable batched channel
+    fn send_batch(&mut self, messages: &[&[u8]], socket: &mut Socket) -> Result<(), Error> {
+        match self.channel_type {
+            ChannelType::SendUnreliableBatched => (),
+            ChannelType::ReceiveUnreliable | ChannelType::ReceiveUnreliableBatched | ChannelType::ReceiveUnreliableSequenced { .. } | ChannelType::ReceiveReliable { .. } | ChannelType::ReceiveFecReliable { .. } => return Err(Error::SendOnReceiveChannel),
+            _ => return Err(Error::NotBatchedChannel),
+        }
 
-impl Connection {
-    fn new(config: &ClientConfig, addr: SocketAddr, instance: &[u8; 16], socket: &mut Socket) -> Result<Self, Error> {
-        let creation_time = Instant::now();
+        // the channel id and one message have to fit in a packet
+        if messages.iter().any(|message| 1 + BATCHED_PREFIX_LEN + message.len() > socket.max_message_size || message.len() > u16::MAX as usize) {
+            return Err(Error::MessageTooLong);
+        }
 
-        socket.heartbeat(addr, instance, creation_time.elapsed().as_millis())?;
+        let mut batched = 0;
 
-        Ok(Connection {
-            addr,
+        for message in messages {
+            if batched > 0 && socket.out_buffer.len() + BATCHED_PREFIX_LEN + message.len() > socket.max_message_size {
+                socket.send(self.addr)?;
+                batched = 0;
+            }
 
-            other_instance: None,
+            if batched == 0 {
+                socket.channel_prefix(self.channel_id)?;
+            }
 
-            creation_time,
-            ping_memory: VecDeque::new(),
-            average_ping: None,
+            socket.write(&wire::batched_prefix(message))?;
+            socket.write(message)?;
+            batched += 1;
+        }
 
-            heartbeat_interval: config.heartbeat_interval,
+        if batched > 0 {
+            socket.send(self.addr)?;
+        }
 
-            last_received_keep_alive: Instant::now(),
-            last_sent_keep_alive: Instant::now(),
+        Ok(())
+    }
+
+    /// takes messages out of an unreliable sequenced channel's jitter buffer, in order,
+    /// that either follow the last message given or have been held for the whole jitter buffer
+    fn release_held(&mut self) -> Vec<ReceivedMessage> {
+        let ChannelType::ReceiveUnreliableSequenced { jitter_buffer: Some(jitter_buffer), next_seq, held } = &mut self.channel_type else {return vec![];};
+
+        let mut released = Vec::new();
 
-            channels: config.channels.iter().enumerate().map(|(id, c)| Channel::new(c, id as u8, addr)).collect(),
+        while let Some(entry) = held.first_entry() {
+            let (arrived, _) = entry.get();
+            if *entry.key() != *next_seq && arrived.elapsed().as_millis() < *jitter_buffer {break;}
+
+            let (seq, (_, message)) = entry.remove_entry();
+            *next_seq = seq.saturating_add(1);
+            released.push((Some(seq), message, false));
+        }
+
+        released
+    }
+
+    /// how long after being sent a message on a reliable or fec send channel is resent before any backoff, see `Endpoint::resend_interval`
+    fn resend_interval(&self, ping: Option<u128>) -> Option<Duration> {
+        let resend_threshhold = match &self.channel_type {
+            ChannelType::SendReliable { resend_threshhold, .. } => *resend_threshhold,
+            ChannelType::SendFecReliable { resend_threshhold, .. } => *resend_threshhold,
+            _ => return None,
+        };
+
+        Some(Duration::try_from_secs_f32(ping? as f32 * resend_threshhold / 1000.).unwrap_or_default())
+    }
+
+    /// time until the soonest resend on a reliable or fec send channel, see `Endpoint::next_retransmit`
+    fn next_retransmit(&self, ping: Option<u128>) -> Option<Duration> {
+        let resend_interval = self.resend_interval(ping)?;
+
+        // when each message was last sent and how many times it's been resent
+        let sent: Vec<(Instant, u32)> = match &self.channel_type {
+            ChannelType::SendReliable { messages, .. } => messages.iter().flatten().filter_map(|message| Some((message.last_sent?, message.retransmits))).collect(),
+            ChannelType::SendFecReliable { messages, .. } => messages.iter().flatten().filter_map(|message| Some((message.last_sent?, message.retransmits))).collect(),
+            _ => return None,
+        };
+
+        sent.into_iter().map(|(last_sent, retransmits)| {
+            resend_interval.saturating_mul(resend_backoff(retransmits) as u32).saturating_sub(last_sent.elapsed())
+        }).min()
+    }
+
+    /// false if sending a message of the length would use up the channel's sequence numbers, see `Error::SequencesExhausted`
+    fn has_sequences_for(&self, message_len: usize, max_message_size: usize) -> bool {
+        match &self.channel_type {
+            ChannelType::SendUnreliableSequenced { seq_counter } => *seq_counter < u64::MAX,
+            ChannelType::SendReliable { seq_counter, lifetime, .. } => {
+                let skip_len = if lifetime.is_some() {ReliableHeader::SKIP_LEN} else {0};
+                let fragments = reliable_fragment_len(message_len, max_message_size, skip_len).map_or(1, |fragment_len| message_len.div_ceil(fragment_len));
+
+                seq_counter.checked_add(fragments as u64).is_some()
+            },
+            ChannelType::SendFecReliable { seq_counter, .. } => *seq_counter < u64::MAX,
+            _ => true,
+        }
+    }
+
+    /// number of sent messages that haven't been acknowledged
+    fn pending_messages(&self) -> usize {
+        match &self.channel_type {
+            ChannelType::SendReliable { messages, .. } => messages.iter().flatten().count(),
+            ChannelType::SendFecReliable { messages, .. } => messages.iter().flatten().count(),
+            _ => 0,
+        }
+    }
+
+    /// true when a send reliable channel has just dropped under it's low water mark, see `ClientConfig::low_water_mark`
+    fn writable(&mut self) -> bool {
+        let (Some(low_water_mark), ChannelType::SendReliable { messages, .. }) = (self.low_water_mark, &self.channel_type) else {return false;};
+
+        if messages.iter().flatten().count() >= low_water_mark {
+            self.above_low_water = true;
+            false
+        } else {
+            std::mem::take(&mut self.above_low_water)
+        }
+    }
+
+    /// bytes of messages waiting for their first send, held back by the rate limit
+    fn queued_bytes(&self) -> usize {
+        match &self.channel_type {
+            ChannelType::SendUnreliable { queue, .. } => queue.iter().map(|(message, _)| message.len()).sum(),
+            ChannelType::SendReliable { messages, .. } => messages.iter().flatten()
+                .filter(|message| message.last_sent.is_none())
+                .map(|message| message.message.len())
+                .sum(),
+            ChannelType::SendFecReliable { messages, .. } => messages.iter().flatten()
+                .filter(|message| message.last_sent.is_none())
+                .flat_map(|message| message.packets.iter().flatten())
+                .map(Vec::len)
+                .sum(),
+            _ => 0,
+        }
+    }
+
+    fn debug_state(&self) -> Option<ChannelDebug> {
+        let (messages_start_seq, seq_counter, outstanding): (u64, u64, Vec<u64>) = match &self.channel_type {
+            ChannelType::SendReliable { messages_start_seq, seq_counter, messages, .. } =>
+                (*messages_start_seq, *seq_counter, (*messages_start_seq..=u64::MAX).zip(messages.iter()).filter(|(_, message)| message.is_some()).map(|(seq, _)| seq).collect()),
+            ChannelType::SendFecReliable { messages_start_seq, seq_counter, messages, .. } =>
+                (*messages_start_seq, *seq_counter, (*messages_start_seq..=u64::MAX).zip(messages.iter()).filter(|(_, message)| message.is_some()).map(|(seq, _)| seq).collect()),
+            _ => return None,
+        };
+
+        Some(ChannelDebug {
+            start_seq: messages_start_seq,
+            next_seq: seq_counter,
+            pending: outstanding.len(),
+            outstanding,
         })
     }
 
-    fn update(&mut self, instance: &[u8; 16], socket: &mut Socket) -> Result<(), Error> {
-        if self.last_sent_keep_alive.elapsed().as_millis() > self.heartbeat_interval {
-            socket.heartbeat(self.addr, instance, self.creation_time.elapsed().as_millis())?;
-            self.last_sent_keep_alive = Instant::now();
+    /// queues a message on a send unreliable channel and sends as much of the queue as the rate limit allows
+    ///
+    /// the message is dropped instead of sent if it's still queued after it expires
+    fn queue_unreliable(&mut self, message: &[u8], expires: Option<Instant>, socket: &mut Socket) -> Result<(), Error> {
+        let ChannelType::SendUnreliable { queue_limit, queue } = &mut self.channel_type else {return Err(Error::NotUnreliableChannel);};
+
+        if message.len() > socket.max_message_size.saturating_sub(1) {
+            return Err(Error::MessageTooLong);
         }
 
-        for channel in self.channels.iter_mut() {
-            channel.update(self.average_ping, socket)?;
+        queue.push_back((Vec::from(message), expires));
+
+        while queue.len() > (*queue_limit).max(1) {
+            queue.pop_front();
+        }
+
+        self.flush_queue(socket)
+    }
+
+    /// sends the messages queued by a send unreliable channel, dropping any that have expired
+    fn flush_queue(&mut self, socket: &mut Socket) -> Result<(), Error> {
+        let ChannelType::SendUnreliable { queue, .. } = &mut self.channel_type else {return Ok(());};
+
+        let now = Instant::now();
+        queue.retain(|(_, expires)| expires.is_none_or(|expires| expires > now));
+
+        while !queue.is_empty() && self.pacer.ready() {
+            let Some((message, _)) = queue.pop_front() else {break;};
+
+            socket.channel_prefix(self.channel_id)?;
+            socket.write(&message)?;
+            let sent = socket.send(self.addr)?;
+            self.pacer.spend(sent);
         }
 
         Ok(())
     }
-}
 
+    /// sends the acks queued by a receive reliable channel
+    ///
+    /// acks are coalesced into ranges of a start sequence and a bitmask of the 64 sequences after it
+    fn flush_acks(&mut self, socket: &mut Socket) -> Result<(), Error> {
+        while let Some(ack) = self.take_ack() {
+            socket.channel_prefix(self.channel_id)?;
+            socket.write(&ack.encode())?;
+            socket.send(self.addr)?;
+        }
 
-pub enum Event {
-    Connection(SocketAddr),
-    Disconnection(SocketAddr, DisconnectReason),
-    Message(SocketAddr, u8, Vec<u8>),
+        Ok(())
+    }
+
+    /// takes the lowest queued ack on a receive reliable channel along with any of the 64 sequences after it that are queued
+    fn take_ack(&mut self) -> Option<ReliableAck> {
+        let ChannelType::ReceiveReliable { acks_to_send, oldest_ack, .. } = &mut self.channel_type else {return None;};
+
+        acks_to_send.sort_unstable();
+        acks_to_send.dedup();
+
+        let &start_seq = acks_to_send.first()?;
+        let mut mask = 0u64;
+        let mut taken = 1;
+
+        for &seq in &acks_to_send[1..] {
+            let bit = seq - start_seq - 1;
+            if bit >= u64::BITS as u64 {break;}
+
+            mask |= 1 << bit;
+            taken += 1;
+        }
+
+        acks_to_send.drain(..taken);
+
+        if acks_to_send.is_empty() {
+            *oldest_ack = None;
+        }
+
+        Some(ReliableAck { start_seq, mask })
+    }
+
+    /// queues acks taken by `Channel::take_ack` again
+    fn requeue_ack(&mut self, ack: ReliableAck) {
+        let ChannelType::ReceiveReliable { acks_to_send, oldest_ack, .. } = &mut self.channel_type else {return;};
+
+        acks_to_send.extend(ack.acked());
+        oldest_ack.get_or_insert_with(Instant::now);
+    }
+
+    fn update(&mut self, ping: Option<u128>, socket: &mut Socket) -> Result<(), Error> {
+        match &mut self.channel_type {
+            ChannelType::SendUnreliable { .. } => self.flush_queue(socket)?,
+            ChannelType::ReceiveUnreliable => (),
+
+            ChannelType::SendUnreliableBatched => (),
+            ChannelType::ReceiveUnreliableBatched => (),
+
+            ChannelType::SendUnreliableSequenced { .. } => (),
+            ChannelType::ReceiveUnreliableSequenced { .. } => (),
+
+            ChannelType::SendReliable { messages, messages_start_seq, resend_threshhold, max_retransmits, abandoned, .. } => {
+                // expired messages are forgotten as if they'd been acknowledged, but never give an `Event::Delivered`
+                let now = Instant::now();
+                for message in messages.iter_mut() {
+                    if message.as_ref().is_some_and(|message| message.expires.is_some_and(|expires| expires <= now)) {
+                        *message = None;
+                        *abandoned = true;
+                    }
+                }
+
+                while let Some(None) = messages.front() {
+                    messages.pop_front();
+                    *messages_start_seq += 1;
+                }
+
+                let skip = abandoned.then_some(*messages_start_seq);
+
+                // send messages deferred by the rate limit, oldest first
+                for (seq, message) in (*messages_start_seq..=u64::MAX).zip(messages.iter_mut()) {
+                    let Some(message) = message else {continue;};
+                    if message.last_sent.is_some() {continue;}
+                    if !self.pacer.ready() {break;}
+
+                    socket.channel_prefix(self.channel_id)?;
+                    socket.write(&ReliableHeader { seq, fragment: message.fragment, ack: None, skip }.encode())?;
+                    socket.write(&message.message)?;
+                    let sent = socket.send(self.addr)?;
+                    self.pacer.spend(sent);
+
+                    message.last_sent = Some(Instant::now());
+                }
+
+                // only resend if ping has been calculated
+                if let Some(ping) = ping {
+
+                    // resend higher priority messages first
+                    let mut to_resend: Vec<_> = (*messages_start_seq..=u64::MAX).zip(messages.iter_mut())
+                        .filter_map(|(seq, message)| Some((seq, message.as_mut()?)))
+                        .filter(|(_, message)| message.last_sent.is_some_and(|last_sent| last_sent.elapsed().as_millis() as f32 > ping as f32 * *resend_threshhold * resend_backoff(message.retransmits)))
+                        .collect();
+                    to_resend.sort_by_key(|(_, message)| std::cmp::Reverse(message.priority));
+
+                    for (seq, message) in to_resend {
+                        if max_retransmits.is_some_and(|max_retransmits| message.retransmits >= max_retransmits) {
+                            self.failed = true;
+                            break;
+                        }
+
+                        if !self.pacer.ready() {break;}
+
+                        socket.channel_prefix(self.channel_id)?;
+                        socket.write(&ReliableHeader { seq, fragment: message.fragment, ack: None, skip }.encode())?;
+                        socket.write(&message.message)?;
+                        let sent = socket.send(self.addr)?;
+                        self.pacer.spend(sent);
+
+                        message.last_sent = Some(Instant::now());
+                        message.retransmits += 1;
+                        self.diagnostics.retransmits += 1;
+                    }
+                }
+            },
+
+            ChannelType::ReceiveReliable { ack_delay, oldest_ack, .. } => {
+                if ack_delay.is_none_or(|ack_delay| oldest_ack.is_some_and(|oldest_ack| oldest_ack.elapsed().as_millis() >= ack_delay)) {
+                    self.flush_acks(socket)?;
+                }
+            },
+
+            ChannelType::SendFecReliable { messages, resend_threshhold, max_retransmits, .. } => {
+                // send messages deferred by the rate limit, oldest first
+                for message in messages.iter_mut().flatten() {
+                    if message.last_sent.is_some() {continue;}
+                    if !self.pacer.ready() {break;}
+
+                    for packet in message.packets.iter().flatten() {
+                        socket.channel_prefix(self.channel_id)?;
+                        socket.write(packet)?;
+                        socket.queue_datagram(self.addr);
+                        self.pacer.spend(packet.len() + 1);
+                    }
+
+                    message.last_sent = Some(Instant::now());
+                }
+
+                // retransmit packets that have not gotten acks
+
+                // only resend if ping has been calculated
+                if let Some(ping) = ping {
+
+                    // resend higher priority messages first
+                    let mut to_resend: Vec<_> = messages.iter_mut().flatten()
+                        .filter(|message| message.last_sent.is_some_and(|last_sent| last_sent.elapsed().as_millis() as f32 > ping as f32 * *resend_threshhold * resend_backoff(message.retransmits)))
+                        .collect();
+                    to_resend.sort_by_key(|message| std::cmp::Reverse(message.priority));
+
+                    for message in to_resend {
+                        if max_retransmits.is_some_and(|max_retransmits| message.retransmits >= max_retransmits) {
+                            self.failed = true;
+                            break;
+                        }
+
+                        if !self.pacer.ready() {break;}
+
+                        for packet in message.packets.iter().flatten() {
+                            socket.channel_prefix(self.channel_id)?;
+                            socket.write(packet)?;
+                            socket.queue_datagram(self.addr);
+                            self.pacer.spend(packet.len() + 1);
+                        }
+
+                        message.last_sent = Some(Instant::now());
+                        message.retransmits += 1;
+                        self.diagnostics.retransmits += 1;
+                    }
+
+                }
+
+                socket.flush_datagrams()?;
+            },
+
+            ChannelType::ReceiveFecReliable { .. } => (),
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
-pub enum DisconnectReason {
-    Kicked,
-    Other,
-    Timeout,
-    OriginChangedInstance,
+pub enum Error {
+    /// returned when trying to create a client with more than 250 channels
+    TooManyChannels,
+    /// returned when trying to send a message that is too long
+    MessageTooLong,
+    /// returned when trying to create a client with a max message size larger than a udp packet can be
+    MaxMessageSizeTooLarge,
+    /// returned when trying to create a client with a max message size too small to fit a heartbeat
+    MaxMessageSizeTooSmall,
+    /// returned when trying to send a message on a channel meant for receiving
+    SendOnReceiveChannel,
+    /// returned when trying to send to an address that doesn't exist
+    AddressNotConnected,
+    /// returned when trying to send to an address that was disconnected during or since the last update
+    ConnectionClosing,
+    /// returned when trying to send anything other than a plain message to a paused connection, see `Endpoint::pause_connection`
+    ConnectionPaused,
+    /// returned when trying to send on a channel id that doesn't exist
+    InvalidChannelId,
+    /// returned when trying to send a batch on a channel that isn't a `ChannelConfig::SendUnreliableBatched`
+    NotBatchedChannel,
+    /// returned when a `ChannelConfig::SendFecReliable` has symbol counts that can't be encoded
+    InvalidFecConfig,
+    /// returned when trying to send with a ttl on a channel that isn't a `ChannelConfig::SendUnreliable`
+    NotUnreliableChannel,
+    /// returned when trying to send a stream or request on a channel that isn't a `ChannelConfig::SendReliable`
+    NotReliableChannel,
+    /// returned when a channel has used every sequence number, the connection has to be made again to keep sending on it
+    ///
+    /// sequence numbers never wrap around, as both sides assume they only go up
+    SequencesExhausted,
+    /// returned when either 0 or more than one connection is present when trying to use Endpoint::send_single
+    SendSingleInvalid,
+    /// returned when trying to use the socket of an endpoint made with `Endpoint::loopback_pair`
+    NoSocket,
+    /// returned when binding with `ClientConfig::bind_device` set anywhere but linux
+    BindDeviceUnsupported,
+    /// returned when a message is read as a string but isn't valid utf-8
+    InvalidUtf8(std::str::Utf8Error),
+    /// returned when an io error is encountered
+    IoError(std::io::Error)
 }
 
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::IoError(value)
+    }
+}
 
-struct Channel {
-    addr: SocketAddr,
-    channel_id: u8,
 
-    channel_type: ChannelType,
-}
 
-enum ChannelType {
-    SendUnreliable,
-    ReceiveUnreliable,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loopback::LOOPBACK_ADDRS;
+
+    /// a config with every option off, for tests to change what they need
+    fn config(listen: bool, channels: Vec<ChannelConfig>) -> ClientConfig {
+        ClientConfig {
+            max_message_size: 1200,
+            heartbeat_interval: 20,
+            timeout: 2000,
+            handshake_timeout: 1000,
+            pause_timeout: None,
+            require_handshake_ack: false,
+            stall_fraction: None,
+            ping_memory_length: 16,
+            min_ping_samples: None,
+            ping_smoothing: None,
+            ack_delay: None,
+            piggyback_acks: false,
+            listen,
+            challenge_handshake: false,
+            strict_peers: false,
+            duplicate_instances: None,
+            expected_connections: 0,
+            reuse_address: false,
+            reuse_port: false,
+            bind_device: None,
+            max_packets_per_update: None,
+            fair_queuing: None,
+            low_water_mark: None,
+            report_invalid_packets: true,
+            sequenced_messages: false,
+            report_gaps: false,
+            receive_batch_size: 1,
+            recv_socket_buffer: None,
+            seed: None,
+            checksum: false,
+            channels,
+        }
+    }
 
-    SendReliable {
-        resend_threshhold: f32,
+    /// a connecting endpoint at `LOOPBACK_ADDRS[0]` and a listening one at `LOOPBACK_ADDRS[1]`, updated until they've connected
+    fn connected(config_a: ClientConfig, config_b: ClientConfig) -> (Endpoint, Endpoint) {
+        connect(Endpoint::loopback_pair(config_a, config_b).unwrap())
+    }
 
-        seq_counter: u64,
+    /// connects the first endpoint of a loopback pair to the second
+    fn connect((mut a, mut b): (Endpoint, Endpoint)) -> (Endpoint, Endpoint) {
+        a.connect(LOOPBACK_ADDRS[1]).unwrap();
 
-        messages_start_seq: u64,
-        messages: VecDeque<Option<(Instant, Vec<u8>)>>
-    },
-    ReceiveReliable {
-        acks_to_send: Vec<u64>,
+        for _ in 0..4 {
+            a.update().unwrap();
+            b.update().unwrap();
+        }
 
-        received_start_seq: u64,
-        received: VecDeque<bool>,
-    },
+        assert!(a.is_reachable(LOOPBACK_ADDRS[1]) && b.is_reachable(LOOPBACK_ADDRS[0]));
+        (a, b)
+    }
 
-    SendFecReliable {
-        resend_threshhold: f32,
+    fn reliable() -> ChannelConfig {
+        ChannelConfig::SendReliable { resend_threshhold: 1.25, adaptive_resend: None, max_retransmits: None, rate_limit: None, lifetime: None }
+    }
 
-        max_data_symbols: usize,
-        max_repair_symbols: usize,
+    fn fec(max_data_symbols: usize, max_repair_symbols: usize) -> ChannelConfig {
+        ChannelConfig::SendFecReliable { resend_threshhold: 1.25, adaptive_resend: None, max_retransmits: None, rate_limit: None, max_data_symbols, max_repair_symbols }
+    }
 
-        seq_counter: u64,
+    fn messages(events: Vec<Event>) -> usize {
+        events.into_iter().filter(Event::is_message).count()
+    }
 
-        messages_start_seq: u64,
-        messages: VecDeque<Option<(Instant, Vec<Option<Vec<u8>>>)>>,
-    },
-    ReceiveFecReliable {
-        messages_start_seq: u64,
-        messages: VecDeque<ReceiveFecMessage>,
-    },
-}
+    #[test]
+    fn acks_are_coalesced() {
+        let config = config(false, vec![ChannelConfig::ReceiveReliable]);
+        let (port, mut peer) = loopback::pair();
+        let mut socket = Socket::new(&config, Transport::Loopback(port));
+        let mut channel = Channel::new(&config.channels[0], 0, LOOPBACK_ADDRS[1], &config);
+
+        for seq in 0..1000 {
+            let mut packet = ReliableHeader { seq, fragment: None, ack: None, skip: None }.encode();
+            packet.push(0);
+            channel.receive(packet, None, &mut socket).unwrap();
+        }
+
+        channel.flush_acks(&mut socket).unwrap();
+
+        let mut acked = Vec::new();
+        while let Some((packet, _)) = peer.receive() {
+            assert_eq!(packet[0], CHANNEL_OFFSET);
+            acked.extend(ReliableAck::decode(&packet[1..]).unwrap().acked());
+        }
+
+        // each ack covers a start sequence and the 64 after it
+        assert_eq!(socket.traffic.packets_sent, 1000u64.div_ceil(65));
+        assert_eq!(acked, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn packets_over_the_cap_wait_for_the_next_update() {
+        let mut sender = config(false, vec![ChannelConfig::SendUnreliable { queue_limit: 64, rate_limit: None }]);
+        let mut receiver = config(true, vec![ChannelConfig::ReceiveUnreliable]);
+        // no heartbeats to count against the cap
+        sender.heartbeat_interval = 10000;
+        receiver.max_packets_per_update = Some(10);
+        let (mut a, mut b) = connected(sender, receiver);
+
+        for i in 0..25 {
+            a.send(LOOPBACK_ADDRS[1], 0, &[i]).unwrap();
+        }
+        a.update().unwrap();
+
+        assert_eq!(messages(b.update().unwrap()), 10);
+        assert_eq!(messages(b.update().unwrap()), 10);
+        assert_eq!(messages(b.update().unwrap()), 5);
+    }
+
+
+    #[test]
+    fn ping_is_measured_without_channels() {
+        let (mut a, mut b) = connected(config(false, vec![]), config(true, vec![]));
+
+        a.ping_now(LOOPBACK_ADDRS[1]).unwrap();
+        b.update().unwrap();
+        a.update().unwrap();
+
+        assert!(a.get_ping(LOOPBACK_ADDRS[1]).unwrap().is_some());
+        assert!(b.get_ping(LOOPBACK_ADDRS[0]).unwrap().is_some());
+    }
+
+
+    #[test]
+    fn connection_timeout_override_fires_first() {
+        let (mut a, mut b) = connected(config(false, vec![]), config(true, vec![]));
+        a.set_connection_timeout(LOOPBACK_ADDRS[1], 100).unwrap();
+
+        // b isn't updated, so a hears nothing from it
+        let start = Instant::now();
+        let timed_out = loop {
+            assert!(start.elapsed().as_millis() < 1000, "the overridden timeout never fired");
+
+            let timed_out = a.update().unwrap().into_iter().any(|event| matches!(event, Event::Disconnection(_, DisconnectReason::Timeout)));
+            if timed_out {break start.elapsed();}
+
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        assert!(timed_out.as_millis() >= 100);
+
+        // b has the default timeout of 2 seconds, so it's still connected after the same silence
+        let disconnected = b.update().unwrap().into_iter().any(|event| matches!(event, Event::Disconnection(..)));
+        assert!(!disconnected);
+        assert_eq!(b.connection_count(), 1);
+    }
+
+
+    #[test]
+    fn handshake_times_out_before_timeout() {
+        let mut connecting = config(false, vec![]);
+        connecting.handshake_timeout = 100;
+        let (mut a, _b) = Endpoint::<()>::loopback_pair(connecting, config(true, vec![])).unwrap();
+
+        // nothing is at the address, so the heartbeats are never echoed
+        let dead_addr = "127.0.0.1:3".parse().unwrap();
+        a.connect(dead_addr).unwrap();
+
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed().as_millis() < 1000, "the handshake never timed out");
+
+            let handshake_timed_out = a.update().unwrap().into_iter().any(|event| matches!(event, Event::Disconnection(addr, DisconnectReason::HandshakeTimeout) if addr == dead_addr));
+            if handshake_timed_out {break;}
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(a.connection_count(), 0);
+    }
+
+
+    #[test]
+    fn max_message_size_too_small_for_a_heartbeat_is_rejected() {
+        let mut too_small = config(false, vec![]);
+        too_small.max_message_size = 0;
+        assert!(matches!(Endpoint::<()>::loopback_pair(too_small, config(true, vec![])), Err(Error::MaxMessageSizeTooSmall)));
 
-enum ReceiveFecMessage {
-    NotSeen,
-    Receiving {
-        decoder: raptor_code::SourceBlockDecoder,
-    },
-    Received,
-}
+        // the checksum comes out of the max message size as well
+        let mut checksummed = config(false, vec![]);
+        checksummed.max_message_size = Socket::HEARTBEAT_HEADER_LEN as u16;
+        checksummed.checksum = true;
+        assert!(matches!(Endpoint::<()>::loopback_pair(checksummed, config(true, vec![])), Err(Error::MaxMessageSizeTooSmall)));
+    }
 
-impl Channel {
-    fn new(config: &ChannelConfig, channel_id: u8, addr: SocketAddr) -> Self {
-        Channel {
-            addr,
-            channel_id,
 
-            channel_type: match config {
-                ChannelConfig::SendUnreliable => ChannelType::SendUnreliable,
-                ChannelConfig::ReceiveUnreliable => ChannelType::ReceiveUnreliable,
+    #[test]
+    fn send_after_disconnect_is_closing() {
+        let (mut a, _b) = connected(config(false, vec![ChannelConfig::SendUnreliable { queue_limit: 1, rate_limit: None }]), config(true, vec![ChannelConfig::ReceiveUnreliable]));
 
-                ChannelConfig::SendReliable { resend_threshhold } => ChannelType::SendReliable {
-                    resend_threshhold: *resend_threshhold,
+        assert!(a.disconnect(LOOPBACK_ADDRS[1]).unwrap());
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 0, b"late"), Err(Error::ConnectionClosing)));
 
-                    seq_counter: 0,
+        // only until the next update, then it's like it was never connected
+        a.update().unwrap();
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 0, b"later"), Err(Error::AddressNotConnected)));
+    }
 
-                    messages_start_seq: 0,
-                    messages: VecDeque::new(),
-                },
-                ChannelConfig::ReceiveReliable => ChannelType::ReceiveReliable {
-                    acks_to_send: Vec::new(),
 
-                    received_start_seq: 0,
-                    received: VecDeque::new(),
-                },
+    #[test]
+    fn unacknowledged_message_fails_after_max_retransmits() {
+        let sender = config(false, vec![ChannelConfig::SendReliable { resend_threshhold: 1.0, adaptive_resend: None, max_retransmits: Some(3), rate_limit: None, lifetime: None }]);
+        let (mut a, _b) = connected(sender, config(true, vec![ChannelConfig::ReceiveReliable]));
 
-                ChannelConfig::SendFecReliable { resend_threshhold, max_data_symbols, max_repair_symbols } => ChannelType::SendFecReliable {
-                    resend_threshhold: *resend_threshhold,
+        // b isn't updated, so the message is never acknowledged
+        a.send(LOOPBACK_ADDRS[1], 0, b"lost").unwrap();
 
-                    max_data_symbols: *max_data_symbols,
-                    max_repair_symbols: *max_repair_symbols,
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed().as_millis() < 1000, "the connection was never dropped");
 
-                    seq_counter: 0,
+            let failed = a.update().unwrap().into_iter().any(|event| matches!(event, Event::Disconnection(_, DisconnectReason::ReliableFailure)));
+            if failed {break;}
 
-                    messages_start_seq: 0,
-                    messages: VecDeque::new(),
-                },
-                ChannelConfig::ReceiveFecReliable => ChannelType::ReceiveFecReliable {
-                    messages_start_seq: 0,
-                    messages: VecDeque::new(),
-                },
-            }
+            std::thread::sleep(Duration::from_millis(5));
         }
+
+        assert_eq!(a.connection_count(), 0);
+        assert_eq!(a.metrics_snapshot().retransmits, 3);
     }
 
-    fn send(&mut self, message: &[u8], socket: &mut Socket) -> Result<(), Error> {
-        socket.channel_prefix(self.channel_id)?;
 
-        match &mut self.channel_type {
-            ChannelType::ReceiveUnreliable => return Err(Error::SendOnReceiveChannel),
-            ChannelType::ReceiveReliable { .. } => return Err(Error::SendOnReceiveChannel),
-            ChannelType::ReceiveFecReliable { .. } => return Err(Error::SendOnReceiveChannel),
+    #[test]
+    fn max_message_size_is_limited_by_address_family() {
+        let bind = |max_message_size, addr: &str| {
+            let mut config = config(false, vec![]);
+            config.max_message_size = max_message_size;
+            Endpoint::<()>::bind(config, addr.parse().unwrap())
+        };
 
+        assert!(bind(65507, "127.0.0.1:0").is_ok());
+        assert!(matches!(bind(65508, "127.0.0.1:0"), Err(Error::MaxMessageSizeTooLarge)));
 
-            ChannelType::SendUnreliable => {
-                socket.write(message)?;
-                socket.send(self.addr)?;
-            },
+        // IPv6 doesn't count it's header against the limit
+        assert!(bind(65527, "[::1]:0").is_ok());
+        assert!(matches!(bind(65528, "[::1]:0"), Err(Error::MaxMessageSizeTooLarge)));
+    }
 
 
-            ChannelType::SendReliable { seq_counter, messages, .. } => {
-                socket.write(&seq_counter.to_be_bytes())?;
-                socket.write(message)?;
-                socket.send(self.addr)?;
+    #[test]
+    fn pending_acks_counts_each_sequence_once() {
+        let mut receiver = config(true, vec![ChannelConfig::ReceiveReliable]);
+        // holds the acks so they can be counted
+        receiver.ack_delay = Some(10000);
+        let (_a, mut b) = connected(config(false, vec![]), receiver);
 
-                messages.push_back(Some((Instant::now(), Vec::from(message))));
-                *seq_counter += 1;
+        for seq in [0, 1, 1, 2] {
+            let mut packet = vec![CHANNEL_OFFSET];
+            packet.extend(ReliableHeader { seq, fragment: None, ack: None, skip: None }.encode());
+            b.handle_packet(packet, LOOPBACK_ADDRS[0]).unwrap();
+        }
 
-            },
+        assert_eq!(b.pending_acks(LOOPBACK_ADDRS[0], 0).unwrap(), 3);
+    }
 
 
-            ChannelType::SendFecReliable { max_data_symbols, max_repair_symbols, seq_counter, messages, .. } => {
+    #[test]
+    fn batch_arrives_as_separate_messages() {
+        let mut sender = config(false, vec![ChannelConfig::SendUnreliableBatched]);
+        sender.heartbeat_interval = 10000;
+        let (mut a, mut b) = connected(sender, config(true, vec![ChannelConfig::ReceiveUnreliableBatched]));
 
-                let (encoded_symbols, num_source_symbols) = raptor_code::encode_source_block(
-                    message,
-                    *max_data_symbols,
-                    *max_repair_symbols,
-                );
+        let received = b.metrics_snapshot().packets_received;
+        a.send_batch(LOOPBACK_ADDRS[1], 0, &[b"one", b"two", b"three"]).unwrap();
+        a.update().unwrap();
 
-                // println!("new fec message {} with {} symbols {:?}", seq_counter, num_source_symbols as usize + *max_repair_symbols, encoded_symbols);
+        let messages: Vec<_> = b.update().unwrap().into_iter().filter_map(|event| match event {
+            Event::Message(_, 0, message) => Some(message),
+            _ => None,
+        }).collect();
 
-                let sequence = seq_counter.to_be_bytes();
-                let num_source_symbols = num_source_symbols.to_be_bytes();
+        assert_eq!(messages, [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        assert_eq!(b.metrics_snapshot().packets_received - received, 1);
+    }
 
 
-                let mut packets = Vec::new();
+    #[test]
+    fn resends_back_off() {
+        let sender = config(false, vec![ChannelConfig::SendReliable { resend_threshhold: 1.0, adaptive_resend: None, max_retransmits: None, rate_limit: None, lifetime: None }]);
+        let (mut a, _b) = connected(sender, config(true, vec![ChannelConfig::ReceiveReliable]));
 
-                for (encoded_symbol_index, encoded_symbol) in encoded_symbols.iter().enumerate() {
-                    let mut packet = Vec::new();
+        // b isn't updated, so every ack is lost and the ping stays where it's set
+        a.connections.get_mut(&LOOPBACK_ADDRS[1]).unwrap().average_ping = Some(10);
+        a.send(LOOPBACK_ADDRS[1], 0, b"lost").unwrap();
 
-                    // 8 bytes
-                    packet.write(&sequence)?;
-                    // 4 bytes
-                    packet.write(&num_source_symbols)?;
-                    // 1 byte
-                    packet.write(&[encoded_symbol_index as u8])?;
-                    // 2 bytes
-                    packet.write(&(message.len() as u16).to_be_bytes())?;
+        let mut resends = vec![Instant::now()];
+        while resends.len() <= 4 {
+            a.update().unwrap();
 
-                    packet.write(&encoded_symbol)?;
+            if a.metrics_snapshot().retransmits as usize == resends.len() {
+                resends.push(Instant::now());
+            }
 
-                    socket.channel_prefix(self.channel_id)?;
-                    socket.write(&packet)?;
-                    socket.send(self.addr)?;
+            std::thread::sleep(Duration::from_millis(1));
+        }
 
-                    packets.push(Some(packet));
-                }
+        let intervals: Vec<_> = resends.windows(2).map(|sent| sent[1] - sent[0]).collect();
+        assert!(intervals.windows(2).all(|intervals| intervals[1] > intervals[0]), "{intervals:?}");
+    }
 
-                messages.push_back(Some((
-                    Instant::now(),
-                    packets,
-                )));
-                *seq_counter += 1;
-            },
+
+    #[test]
+    fn gap_is_reported_once_as_a_range() {
+        let mut receiver = config(true, vec![ChannelConfig::ReceiveReliable]);
+        receiver.report_gaps = true;
+        let (_a, mut b) = connected(config(false, vec![]), receiver);
+
+        for seq in [0, 60000, 5] {
+            let mut packet = vec![CHANNEL_OFFSET];
+            packet.extend(ReliableHeader { seq, fragment: None, ack: None, skip: None }.encode());
+            b.handle_packet(packet, LOOPBACK_ADDRS[0]).unwrap();
         }
 
-        Ok(())
+        let gaps: Vec<_> = b.update().unwrap().into_iter().filter_map(|event| match event {
+            Event::Gap { missing_seqs, .. } => Some(Err(missing_seqs)),
+            Event::GapFilled { seq, .. } => Some(Ok(seq)),
+            _ => None,
+        }).collect();
+
+        assert_eq!(gaps, [Err(1..60000), Ok(5)]);
     }
 
-    fn receive(&mut self, message: Vec<u8>, socket: &mut Socket) -> Result<Vec<Vec<u8>>, Error> {
-        let _ = socket;
 
-        Ok(match &mut self.channel_type {
-            ChannelType::SendUnreliable => vec![],
+    #[test]
+    fn heartbeat_payload_fills_the_rest_of_the_packet() {
+        let (mut a, _b) = connected(config(false, vec![]), config(true, vec![]));
 
-            ChannelType::ReceiveUnreliable => vec![message],
+        let payload_len = a.mtu() - Socket::HEARTBEAT_HEADER_LEN;
+        assert!(a.set_heartbeat_payload(&vec![0; payload_len]).is_ok());
+        assert!(matches!(a.set_heartbeat_payload(&vec![0; payload_len + 1]), Err(Error::MessageTooLong)));
 
-            ChannelType::SendReliable { messages_start_seq, messages, .. } => 'b: {
+        // the largest payload that was accepted still fits in a heartbeat
+        a.ping_now(LOOPBACK_ADDRS[1]).unwrap();
+    }
 
-                let Some(bytes) = message.get(..8) else {break 'b vec![];};
-                let seq = u64::from_be_bytes(bytes.try_into().unwrap());
 
-                if seq < *messages_start_seq {break 'b vec![];}
+    #[test]
+    fn invalid_fec_configs_are_rejected() {
+        // no symbols, symbol counts `raptor_code` can't encode, and more symbols than the index byte can count
+        for (max_data_symbols, max_repair_symbols) in [(0, 4), (2, 4), (3, 4), (250, 6), (usize::MAX, 1)] {
+            let sending = config(false, vec![fec(max_data_symbols, max_repair_symbols)]);
+            assert!(matches!(Endpoint::<()>::loopback_pair(sending, config(true, vec![])), Err(Error::InvalidFecConfig)), "{max_data_symbols} data and {max_repair_symbols} repair symbols");
 
-                // will fail if seq hasn't been sent
-                let Some(entry) = messages.get_mut((seq - *messages_start_seq) as usize) else {break 'b vec![];};
+            let (mut a, _b) = Endpoint::<()>::loopback_pair(config(false, vec![]), config(true, vec![])).unwrap();
+            assert!(matches!(a.add_channel(fec(max_data_symbols, max_repair_symbols)), Err(Error::InvalidFecConfig)));
+        }
 
-                // mark entry as received
-                *entry = None;
+        for (max_data_symbols, max_repair_symbols) in [(1, 0), (4, 0), (200, 55)] {
+            assert!(Endpoint::<()>::loopback_pair(config(false, vec![fec(max_data_symbols, max_repair_symbols)]), config(true, vec![])).is_ok());
+        }
+    }
 
-                while let Some(None) = messages.front() {
-                    messages.pop_front();
-                    *messages_start_seq += 1;
-                }
 
-                vec![]
-            },
+    #[test]
+    fn fec_symbols_are_limited_to_the_index_byte() {
+        let sending = config(false, vec![fec(200, 56)]);
+        assert!(matches!(Endpoint::<()>::loopback_pair(sending, config(true, vec![])), Err(Error::InvalidFecConfig)));
 
-            ChannelType::ReceiveReliable { acks_to_send, received_start_seq, received } => 'b: {
-                // only return messages with sequence numbers that haven't been seen
+        // every one of the 255 symbols allowed gets a distinct index, so the message decodes intact
+        let mut sending = config(false, vec![fec(200, 55)]);
+        sending.heartbeat_interval = 10000;
+        let (mut a, mut b) = connected(sending, config(true, vec![ChannelConfig::ReceiveFecReliable]));
 
-                let Some(bytes) = message.get(..8) else {break 'b vec![];};
-                let seq = u64::from_be_bytes(bytes.try_into().unwrap());
+        let message: Vec<u8> = (0..a.max_payload(0).unwrap()).map(|i| (i * 7) as u8).collect();
+        let sent = a.metrics_snapshot().packets_sent;
+        a.send(LOOPBACK_ADDRS[1], 0, &message).unwrap();
+        a.update().unwrap();
+        assert_eq!(a.metrics_snapshot().packets_sent - sent, 255);
 
-                acks_to_send.push(seq);
+        let received: Vec<_> = b.update().unwrap().into_iter().filter_map(|event| match event {
+            Event::Message(_, 0, message) => Some(message),
+            _ => None,
+        }).collect();
 
-                if seq < *received_start_seq {break 'b vec![];}
+        assert_eq!(received, [message]);
+    }
 
-                let i = (seq - *received_start_seq) as usize;
-                let seen = loop {
-                    match received.get_mut(i) {
-                        None => received.push_back(false),
-                        Some(e) => break e,
-                    }
-                };
 
-                if *seen {break 'b vec![];}
+    #[test]
+    fn multi_megabyte_stream_arrives_intact() {
+        let (mut a, mut b) = connected(config(false, vec![reliable()]), config(true, vec![ChannelConfig::ReceiveReliableStream]));
 
-                *seen = true;
+        let data: Vec<u8> = (0..4_000_000u32).map(|i| (i ^ i >> 8 ^ i >> 16) as u8).collect();
+        let stream_id = a.send_stream(LOOPBACK_ADDRS[1], 0, &data[..]).unwrap();
 
-                while let Some(false) = received.front() {
-                    received.pop_front();
-                    *received_start_seq += 1;
+        let start = Instant::now();
+        let received = 'received: loop {
+            assert!(start.elapsed().as_secs() < 10, "the stream never completed");
+
+            a.update().unwrap();
+            for event in b.update().unwrap() {
+                if let Event::Stream { stream_id: received_id, data, .. } = event {
+                    assert_eq!(received_id, stream_id);
+                    break 'received data;
                 }
+            }
+        };
 
-                vec![Vec::from(&message[8..])]
-            },
+        assert!(received == data, "the stream arrived corrupted");
+    }
 
-            ChannelType::SendFecReliable { messages_start_seq, messages, .. } => {
-                match message.get(0) {
-                    // whole message received acknowledgement
-                    Some(0) => 'b: {
-                        let Some(seq_id) = message.get(1..9) else {break 'b;};
-                        let seq_id = u64::from_be_bytes(seq_id.try_into().unwrap());
 
-                        // println!("got ack for full fec message {}", seq_id);
+    #[test]
+    fn send_multi_sends_nothing_if_any_channel_would_fail() {
+        let mut sender = config(false, vec![ChannelConfig::SendUnreliable { queue_limit: 1, rate_limit: None }, reliable()]);
+        sender.heartbeat_interval = 10000;
+        let (mut a, _b) = connected(sender, config(true, vec![ChannelConfig::ReceiveUnreliable, ChannelConfig::ReceiveReliable]));
 
-                        if seq_id < *messages_start_seq {break 'b;}
+        // the reliable channel has no sequences left
+        let ChannelType::SendReliable { seq_counter, .. } = &mut a.connections.get_mut(&LOOPBACK_ADDRS[1]).unwrap().channels[1].channel_type else {unreachable!()};
+        *seq_counter = u64::MAX;
 
-                        if let Some(message) = messages.get_mut((seq_id - *messages_start_seq) as usize) {
-                            // mark message as received
-                            *message = None;
+        let sent = a.metrics_snapshot().packets_sent;
+        assert!(matches!(a.send_multi(LOOPBACK_ADDRS[1], &[0, 1], b"both"), Err(Error::SequencesExhausted)));
+        assert!(matches!(a.send_multi(LOOPBACK_ADDRS[1], &[0, 2], b"both"), Err(Error::InvalidChannelId)));
+        a.update().unwrap();
+        assert_eq!(a.metrics_snapshot().packets_sent, sent);
 
-                            // clear front of message ring buffer
-                            while let Some(None) = messages.front() {
-                                messages.pop_front();
-                                *messages_start_seq += 1;
-                            }
-                        }
-                    },
-                    Some(1) => 'b: {
-                        // single symbol/packet received acknowledgement
-                        let (
-                            Some(seq_id),
-                            Some(symbol_index),
-                         ) = (
-                            message.get(1..9),
-                            message.get(9)
-                        ) else {break 'b;};
-                        let seq_id = u64::from_be_bytes(seq_id.try_into().unwrap());
-
-                        // println!("got ack for fec symbol {} {}", seq_id, symbol_index);
+        a.disconnect(LOOPBACK_ADDRS[1]).unwrap();
+        assert!(matches!(a.send_multi(LOOPBACK_ADDRS[1], &[0], b"late"), Err(Error::ConnectionClosing)));
+    }
 
-                        if seq_id < *messages_start_seq {break 'b;}
 
-                        if let Some(message) = messages.get_mut((seq_id - *messages_start_seq) as usize) {
-                            if let Some((_, symbols)) = message {
-                                if let Some(symbol) = symbols.get_mut(*symbol_index as usize) {
-                                    // mark packet/symbol as received
-                                    *symbol = None;
+    #[test]
+    fn ordered_channel_puts_reordered_packets_back_in_order() {
+        let sender = config(false, vec![fec(1, 0), ChannelConfig::SendUnreliable { queue_limit: 1, rate_limit: None }]);
+        let receiver = config(true, vec![ChannelConfig::ReceiveFecReliableOrdered, ChannelConfig::ReceiveUnreliable]);
+        let (mut a, mut b) = connect(Endpoint::loopback_pair_reordered(sender, receiver, 8, 7).unwrap());
 
-                                    // mark as sent if every packet gets acknowledged
-                                    if !symbols.iter().any(|e| e.is_some()) {
-                                        *message = None;
+        for i in 0..100u8 {
+            a.send(LOOPBACK_ADDRS[1], 0, &[i]).unwrap();
+            a.send(LOOPBACK_ADDRS[1], 1, &[i]).unwrap();
+        }
 
-                                        // clear front of message ring buffer
-                                        while let Some(Some(_)) = messages.front() {
-                                            messages.pop_front();
-                                            *messages_start_seq += 1;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    },
+        let mut ordered = Vec::new();
+        let mut unordered = Vec::new();
+        for _ in 0..20 {
+            a.update().unwrap();
+
+            for event in b.update().unwrap() {
+                match event {
+                    Event::Message(_, 0, message) => ordered.extend(message),
+                    Event::Message(_, 1, message) => unordered.extend(message),
                     _ => (),
                 }
+            }
+        }
 
-                vec![]
-            },
+        let sent: Vec<u8> = (0..100).collect();
+        assert_eq!(ordered, sent);
 
-            ChannelType::ReceiveFecReliable { messages, messages_start_seq, .. } => 'b: {
+        // the unreliable channel shows the packets really were reordered
+        assert_ne!(unordered, sent);
+        unordered.sort();
+        assert_eq!(unordered, sent);
+    }
 
-                // get header values
-                let (
-                    Some(seq_id),
-                    Some(num_source_symbols),
-                    Some(symbol_index),
-                    Some(message_length),
-                ) = (
-                    message.get(0..8),
-                    message.get(8..12),
-                    message.get(12..13),
-                    message.get(13..15),
-                ) else {break 'b vec![];};
 
-                let seq_id = u64::from_be_bytes(seq_id.try_into().unwrap());
-                let num_source_symbols = u32::from_be_bytes(num_source_symbols.try_into().unwrap());
-                let symbol_index = u8::from_be_bytes(symbol_index.try_into().unwrap());
-                let source_block_length = u16::from_be_bytes(message_length.try_into().unwrap());
+    #[test]
+    fn sequences_run_out_without_wrapping() {
+        let sender = config(false, vec![reliable(), fec(1, 0), ChannelConfig::SendUnreliableSequenced]);
+        let receiver = config(true, vec![ChannelConfig::ReceiveReliable, ChannelConfig::ReceiveFecReliable, ChannelConfig::ReceiveUnreliableSequenced { jitter_buffer: None }]);
+        let (mut a, _b) = connected(sender, receiver);
 
-                // println!("got fec symbol for sequence {} index {}", seq_id, symbol_index);
+        // start every channel with two sequences left
+        for channel in a.connections.get_mut(&LOOPBACK_ADDRS[1]).unwrap().channels.iter_mut() {
+            match &mut channel.channel_type {
+                ChannelType::SendReliable { seq_counter, messages_start_seq, .. } | ChannelType::SendFecReliable { seq_counter, messages_start_seq, .. } => {
+                    *seq_counter = u64::MAX - 1;
+                    *messages_start_seq = u64::MAX - 1;
+                },
+                ChannelType::SendUnreliableSequenced { seq_counter } => *seq_counter = u64::MAX - 1,
+                _ => unreachable!(),
+            }
+        }
 
-                // get the entry for the given seq_id in the receiving messages ring buffer
-                if seq_id < *messages_start_seq {
-                    // send ack for full message received
-                    socket.channel_prefix(self.channel_id)?;
-                    socket.write(&[0])?;
-                    socket.write(&seq_id.to_be_bytes())?;
-                    socket.send(self.addr)?;
+        // a message too long for one packet needs more sequences than are left
+        let fragmented = vec![0; a.mtu() * 2];
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 0, &fragmented), Err(Error::SequencesExhausted)));
 
-                    break 'b vec![];
-                } else {
-                    // send ack for single symbol received
-                    socket.channel_prefix(self.channel_id)?;
-                    socket.write(&[1])?;
-                    socket.write(&seq_id.to_be_bytes())?;
-                    socket.write(&[symbol_index])?;
-                    socket.send(self.addr)?;
-                }
+        assert_eq!(a.send(LOOPBACK_ADDRS[1], 0, b"last").unwrap().map(|handle| handle.seq), Some(u64::MAX - 1));
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 0, b"over"), Err(Error::SequencesExhausted)));
 
-                let index = (seq_id - *messages_start_seq) as usize;
+        // fec and sequenced channels hold `u64::MAX` back as the counter after the last sequence
+        assert_eq!(a.send(LOOPBACK_ADDRS[1], 1, b"last").unwrap().map(|handle| handle.seq), Some(u64::MAX - 1));
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 1, b"over"), Err(Error::SequencesExhausted)));
 
-                let receiving_message = loop {
-                    match messages.get_mut(index) {
-                        None => messages.push_back(ReceiveFecMessage::NotSeen),
-                        Some(message) => break message,
-                    }
-                };
+        a.send(LOOPBACK_ADDRS[1], 2, b"last").unwrap();
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 2, b"over"), Err(Error::SequencesExhausted)));
 
-                // if not seen yet initialize the decoder
-                if let ReceiveFecMessage::NotSeen = receiving_message {
-                    *receiving_message = ReceiveFecMessage::Receiving {
-                        decoder: raptor_code::SourceBlockDecoder::new(num_source_symbols as usize,),
-                    };
-                }
+        // running out only stops sending, the connection stays up
+        a.update().unwrap();
+        assert_eq!(a.connection_count(), 1);
+    }
 
-                // get the decoder
-                let decoder = match receiving_message {
-                    ReceiveFecMessage::NotSeen => unreachable!(),
-                    ReceiveFecMessage::Received => {
-                        // send ack for full message received
-                        socket.channel_prefix(self.channel_id)?;
-                        socket.write(&[0])?;
-                        socket.write(&seq_id.to_be_bytes())?;
-                        socket.send(self.addr)?;
 
-                        break 'b vec![];
-                    },
-                    ReceiveFecMessage::Receiving { decoder } => decoder,
-                };
+    #[test]
+    fn held_messages_are_checked_and_reported_on_resume() {
+        let (mut a, _b) = connected(config(false, vec![reliable()]), config(true, vec![ChannelConfig::ReceiveReliable]));
 
-                // push the symbol to the decoder
-                decoder.push_encoding_symbol(&message[15..], symbol_index as u32);
+        let ChannelType::SendReliable { seq_counter, messages_start_seq, .. } = &mut a.connections.get_mut(&LOOPBACK_ADDRS[1]).unwrap().channels[0].channel_type else {unreachable!()};
+        *seq_counter = u64::MAX - 1;
+        *messages_start_seq = u64::MAX - 1;
 
-                // check if decoding is possible
-                if decoder.fully_specified() {
-                    let message = decoder.decode(source_block_length as usize).unwrap();
+        a.pause_connection(LOOPBACK_ADDRS[1]).unwrap();
 
-                    *receiving_message = ReceiveFecMessage::Received;
+        // anything that would fail now is rejected rather than held
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 1, b"held"), Err(Error::InvalidChannelId)));
+        assert!(matches!(a.send(LOOPBACK_ADDRS[1], 0, &vec![0; a.mtu() * 2]), Err(Error::SequencesExhausted)));
 
-                    // send ack for full message received
-                    socket.channel_prefix(self.channel_id)?;
-                    socket.write(&[0])?;
-                    socket.write(&seq_id.to_be_bytes())?;
-                    socket.send(self.addr)?;
+        // each fits in the one sequence left on it's own, but only the first once they're both sent
+        assert!(a.send(LOOPBACK_ADDRS[1], 0, b"first").unwrap().is_none());
+        assert!(a.send(LOOPBACK_ADDRS[1], 0, b"second").unwrap().is_none());
 
-                    // clear the front of the receiving ring buffer
-                    while let Some(ReceiveFecMessage::Received) = messages.front() {
-                        messages.pop_front();
-                        *messages_start_seq += 1;
-                    }
+        a.resume_connection(LOOPBACK_ADDRS[1]).unwrap();
+        let events = a.update().unwrap();
 
-                    vec![message]
-                } else {
-                    vec![]
-                }
-            }
-        })
+        assert!(events.iter().any(|event| matches!(event, Event::QueuedSent(_, MessageHandle { channel_id: 0, seq }) if *seq == u64::MAX - 1)));
+        assert!(events.iter().any(|event| matches!(event, Event::SendFailed { channel_id: 0, message, error: Error::SequencesExhausted, .. } if message == b"second")));
     }
 
-    fn update(&mut self, ping: Option<u128>, socket: &mut Socket) -> Result<(), Error> {
-        match &mut self.channel_type {
-            ChannelType::SendUnreliable => (),
-            ChannelType::ReceiveUnreliable => (),
 
-            ChannelType::SendReliable { messages, messages_start_seq, resend_threshhold, .. } => {
-                // only resend if ping has been calculated
-                if let Some(ping) = ping {
+    #[test]
+    fn client_pool_updates_every_client_on_its_workers() {
+        let mut pool = ClientPool::new(3);
+        let mut servers = Vec::new();
 
-                    let mut seq = *messages_start_seq;
-                    for message in messages.iter_mut() {
-                        if let Some((last_sent, message)) = message {
+        for _ in 0..5 {
+            let (client, server) = Client::loopback(config(false, vec![reliable()]), config(true, vec![ChannelConfig::ReceiveReliable])).unwrap();
+            pool.push(client.with_context::<u32>());
+            servers.push(server);
+        }
 
-                            if last_sent.elapsed().as_millis() as f32 > ping as f32 * *resend_threshhold {
-                                socket.channel_prefix(self.channel_id)?;
-                                socket.write(&seq.to_be_bytes())?;
-                                socket.write(&*message)?;
-                                socket.send(self.addr)?;
+        for _ in 0..4 {
+            let results = pool.update();
+            assert_eq!(results.iter().map(|(index, _)| *index).collect::<Vec<_>>(), (0..5).collect::<Vec<_>>());
+            assert!(results.iter().all(|(_, result)| result.is_ok()));
 
-                                *last_sent = Instant::now();
-                            }
-                        }
+            for server in servers.iter_mut() {
+                server.update().unwrap();
+            }
+        }
 
-                        seq += 1;
-                    }
-                }
-            },
+        // the pool hands back the same clients, each still at it's own index
+        for (index, client) in pool.clients_mut().enumerate() {
+            *client.context_mut().unwrap() = index as u32;
+        }
 
-            ChannelType::ReceiveReliable { acks_to_send, .. } => {
-                for seq in acks_to_send.drain(..) {
-                    socket.channel_prefix(self.channel_id)?;
-                    socket.write(&seq.to_be_bytes())?;
-                    socket.send(self.addr)?;
-                }
-            },
+        for (index, mut client) in pool.into_clients().into_iter().enumerate() {
+            assert_eq!(client.context().copied(), Some(index as u32));
+            assert!(client.wait_connected(Duration::ZERO).unwrap());
+            client.send(0, b"hello").unwrap();
+        }
+    }
 
-            ChannelType::SendFecReliable { messages, resend_threshhold, messages_start_seq, .. } => {
-                // retransmit packets that have not gotten acks
 
-                // only resend if ping has been calculated
-                if let Some(ping) = ping {
+    #[test]
+    fn failed_request_does_not_use_an_id() {
+        let (mut a, _b) = connected(config(false, vec![reliable(), ChannelConfig::SendUnreliableSequenced]), config(true, vec![ChannelConfig::ReceiveReliableRequests, ChannelConfig::ReceiveUnreliableSequenced { jitter_buffer: None }]));
 
-                    for message in messages.iter_mut() {
-                        if let Some((last_sent, symbols)) = message {
+        let first = a.request(LOOPBACK_ADDRS[1], 0, b"first").unwrap();
 
-                            if last_sent.elapsed().as_millis() as f32 > ping as f32 * *resend_threshhold {
-                                for symbol in symbols.iter() {
-                                    if let Some(packet) = symbol {
-                                        // println!("retransmitting an fec symbol");
-                                        socket.channel_prefix(self.channel_id)?;
-                                        socket.write(&packet)?;
-                                        socket.send(self.addr)?;
-                                    }
-                                }
+        assert!(matches!(a.request(LOOPBACK_ADDRS[1], 1, b"unreliable"), Err(Error::NotReliableChannel)));
+        assert!(matches!(a.request(LOOPBACK_ADDRS[1], 0, &vec![0; a.max_payload(0).unwrap() + 1]), Err(Error::MessageTooLong)));
+        assert!(matches!(a.request(LOOPBACK_ADDRS[0], 0, b"nobody"), Err(Error::AddressNotConnected)));
 
-                                *last_sent = Instant::now();
-                            }
-                        }
-                    }
-                }
+        let second = a.request(LOOPBACK_ADDRS[1], 0, b"second").unwrap();
+        assert_eq!(second.id, first.id + 1);
+    }
 
-                // let mut n = *messages_start_seq;
-                // for message in messages.iter() {
-                //     if n != *messages_start_seq {
-                //         print!(", ")
-                //     }
 
-                //     if message.is_some() {
-                //         print!("{}", n);
-                //     } else {
-                //         print!("{}", "_".repeat(n.to_string().len()));
-                //     }
+    #[test]
+    fn injected_packets_go_through_update() {
+        let mut receiving = config(true, vec![ChannelConfig::ReceiveReliable]);
+        receiving.report_invalid_packets = true;
+        let (_a, mut b) = connected(config(false, vec![reliable()]), receiving);
 
-                //     n += 1;
-                // }
+        let received = b.metrics_snapshot().packets_received;
 
-                // println!();
+        b.inject_raw(LOOPBACK_ADDRS[0], &[]);
+        b.inject_raw(LOOPBACK_ADDRS[0], &[CHANNEL_OFFSET + 7, 0, 0]);
+        b.inject_raw(LOOPBACK_ADDRS[0], &[RAW_PREFIX, 1, 2, 3]);
 
-            },
+        let events = b.update().unwrap();
 
-            ChannelType::ReceiveFecReliable { .. } => (),
+        assert!(events.iter().any(|event| matches!(event, Event::InvalidPacket { reason: InvalidReason::Empty, .. })));
+        assert!(events.iter().any(|event| matches!(event, Event::InvalidPacket { reason: InvalidReason::UnknownChannel, .. })));
+        assert!(events.iter().any(|event| matches!(event, Event::Raw(addr, bytes) if *addr == LOOPBACK_ADDRS[0] && bytes == &[1, 2, 3])));
+        assert_eq!(b.metrics_snapshot().packets_received, received + 3);
+
+        // malformed packets don't cost the connection anything
+        assert!(b.is_reachable(LOOPBACK_ADDRS[0]));
+    }
+
+
+    #[test]
+    fn fec_messages_over_the_max_payload_are_rejected() {
+        // limited by the u16 length in the header, then by the number of symbols
+        let (mut a, _b) = connected(config(false, vec![fec(200, 10), fec(4, 2)]), config(true, vec![ChannelConfig::ReceiveFecReliable, ChannelConfig::ReceiveFecReliable]));
+
+        for channel_id in 0..2 {
+            let max_payload = a.max_payload(channel_id).unwrap();
+
+            assert!(matches!(a.send(LOOPBACK_ADDRS[1], channel_id, &vec![0; max_payload + 1]), Err(Error::MessageTooLong)));
+            assert!(a.send(LOOPBACK_ADDRS[1], channel_id, &vec![0; max_payload]).unwrap().is_some());
         }
 
-        Ok(())
+        assert_eq!(a.max_payload(0).unwrap(), u16::MAX as usize);
     }
-}
 
-#[derive(Debug)]
-pub enum Error {
-    /// returned when trying to create a client with more than 253 channels
-    TooManyChannels,
-    /// returned when trying to send a message that is too long
-    MessageTooLong,
-    /// returned when trying to send a message on a channel meant for receiving
-    SendOnReceiveChannel,
-    /// returned when trying to send to an address that doesn't exist
-    AddressNotConnected,
-    /// returned when trying to send on a channel id that doesn't exist
-    InvalidChannelId,
-    /// returned when either 0 or more than one connection is present when trying to use Client::send_single
-    SendSingleInvalid,
-    /// returned when an io error is encountered
-    IoError(std::io::Error)
-}
 
-impl From<std::io::Error> for Error {
-    fn from(value: std::io::Error) -> Self {
-        Error::IoError(value)
+    #[test]
+    fn challenged_connection_answers_with_the_keyed_token() {
+        let mut listening = config(true, vec![ChannelConfig::ReceiveReliable]);
+        listening.challenge_handshake = true;
+        let (_a, b) = connected(config(false, vec![reliable()]), listening);
+
+        // another key can't make the same token, so the token isn't guessable from the address and interval
+        let interval = b.challenge_epoch.elapsed().as_millis() / CHALLENGE_INTERVAL;
+        assert_ne!(RandomState::new().hash_one((LOOPBACK_ADDRS[0], interval)), b.challenge_key.hash_one((LOOPBACK_ADDRS[0], interval)));
     }
-}
 
 
+    #[test]
+    fn client_pool_keeps_every_client_through_a_panic() {
+        let mut pool = ClientPool::new(2);
+        let mut servers = Vec::new();
+
+        for index in 0..4 {
+            let (client, server) = Client::loopback(config(false, vec![reliable()]), config(true, vec![ChannelConfig::ReceiveReliable])).unwrap();
+            let mut client = client.with_context::<u32>();
+            *client.context_mut().unwrap() = index;
+            pool.push(client);
+            servers.push(server);
+        }
+
+        // a state sequences can't reach, so the next update overflows the start of the channel
+        let poison = |client: &mut Client<u32>, poisoned: bool| {
+            let server_addr = client.server_addr();
+            let ChannelType::SendReliable { messages, messages_start_seq, .. } = &mut client.endpoint_mut().connections.get_mut(&server_addr).unwrap().channels[0].channel_type else {unreachable!()};
+            *messages = if poisoned {VecDeque::from([None])} else {VecDeque::new()};
+            *messages_start_seq = if poisoned {u64::MAX} else {0};
+        };
+
+        poison(pool.client_mut(1).unwrap(), true);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.update())).is_err());
+
+        // the clients on the worker that didn't panic are back too, each at it's own index
+        assert_eq!(pool.len(), 4);
+        assert_eq!(pool.clients().map(|client| *client.context().unwrap()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        poison(pool.client_mut(1).unwrap(), false);
+        assert!(pool.update().iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(pool.push(Client::loopback(config(false, vec![]), config(true, vec![])).unwrap().0.with_context()), 4);
+    }
+
+
+    #[test]
+    #[cfg(not(target_os = "linux"))]
+    fn bind_device_is_rejected_off_linux() {
+        let mut pinned = config(true, vec![]);
+        pinned.bind_device = Some("eth0".to_string());
 
+        assert!(matches!(Endpoint::<()>::bind(pinned, "127.0.0.1:0".parse().unwrap()), Err(Error::BindDeviceUnsupported)));
+    }
+}
@@ -0,0 +1,161 @@
+//! an in process transport that passes packets between a pair of endpoints through channels instead of a socket
+
+use std::{net::{SocketAddr, SocketAddrV4, Ipv4Addr}, sync::mpsc::{self, Sender, Receiver}, collections::VecDeque};
+
+use crate::Rng;
+
+
+/// the addresses the two ends of a loopback pair see themselves and each other as
+pub(crate) const LOOPBACK_ADDRS: [SocketAddr; 2] = [
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1)),
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 2)),
+];
+
+
+/// one end of a loopback pair
+pub(crate) struct LoopbackPort {
+    addr: SocketAddr,
+    peer_addr: SocketAddr,
+
+    outbox: Sender<Vec<u8>>,
+    inbox: Receiver<Vec<u8>>,
+
+    /// the last packet received, held so it can be returned by reference like a socket's buffer
+    received: Vec<u8>,
+
+    /// set to shuffle received packets, see `Endpoint::loopback_pair_reordered`
+    reorder: Option<Reorder>,
+}
+
+/// packets that have arrived at a port but not been received, which are received in a random order
+struct Reorder {
+    /// how many of the oldest held packets the next one received is picked from
+    window: usize,
+    rng: Rng,
+    held: VecDeque<Vec<u8>>,
+}
+
+/// creates two ports that send to each other, at `LOOPBACK_ADDRS[0]` and `LOOPBACK_ADDRS[1]`
+pub(crate) fn pair() -> (LoopbackPort, LoopbackPort) {
+    let (a_outbox, b_inbox) = mpsc::channel();
+    let (b_outbox, a_inbox) = mpsc::channel();
+
+    (
+        LoopbackPort {
+            addr: LOOPBACK_ADDRS[0],
+            peer_addr: LOOPBACK_ADDRS[1],
+
+            outbox: a_outbox,
+            inbox: a_inbox,
+
+            received: Vec::new(),
+
+            reorder: None,
+        },
+        LoopbackPort {
+            addr: LOOPBACK_ADDRS[1],
+            peer_addr: LOOPBACK_ADDRS[0],
+
+            outbox: b_outbox,
+            inbox: b_inbox,
+
+            received: Vec::new(),
+
+            reorder: None,
+        },
+    )
+}
+
+impl LoopbackPort {
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// returns the number of bytes sent
+    ///
+    /// packets to any address other than the peer, or sent after the peer has been dropped, are lost like udp packets with nothing to receive them
+    pub(crate) fn send_to(&self, packet: &[u8], addr: SocketAddr) -> usize {
+        if addr == self.peer_addr {
+            let _ = self.outbox.send(packet.to_vec());
+        }
+
+        packet.len()
+    }
+
+    /// makes the port receive each packet at a random position within `window` of where it arrived,
+    /// the same every time for the same seed and packets
+    pub(crate) fn reorder(&mut self, window: usize, seed: u64) {
+        self.reorder = Some(Reorder {
+            window: window.max(1),
+            rng: Rng::new(Some(seed)),
+            held: VecDeque::new(),
+        });
+    }
+
+    /// returns the next packet from the peer without blocking
+    pub(crate) fn receive(&mut self) -> Option<(&[u8], SocketAddr)> {
+        self.received = match &mut self.reorder {
+            None => self.inbox.try_recv().ok()?,
+            Some(reorder) => {
+                reorder.held.extend(self.inbox.try_iter());
+
+                let window = reorder.held.len().min(reorder.window);
+                if window == 0 {return None;}
+
+                let index = (reorder.rng.next_u64() % window as u64) as usize;
+                reorder.held.remove(index)?
+            },
+        };
+
+        Some((&self.received, self.peer_addr))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// sends a packet for each number and returns them in the order they were received
+    fn received_order(window: usize, seed: u64) -> Vec<u8> {
+        let (a, mut b) = pair();
+        b.reorder(window, seed);
+
+        for i in 0..50 {
+            a.send_to(&[i], LOOPBACK_ADDRS[1]);
+        }
+
+        std::iter::from_fn(|| b.receive().map(|(packet, _)| packet[0])).collect()
+    }
+
+    #[test]
+    fn reorder_is_deterministic_within_the_window() {
+        let sent: Vec<u8> = (0..50).collect();
+
+        let order = received_order(4, 1);
+        assert_ne!(order, sent);
+        assert_eq!(order, received_order(4, 1));
+
+        // a packet is picked from the oldest in the window, so nothing jumps further ahead than that
+        for (position, packet) in order.iter().enumerate() {
+            assert!((*packet as usize) < position + 4);
+        }
+
+        let mut sorted = order;
+        sorted.sort();
+        assert_eq!(sorted, sent);
+
+        assert_eq!(received_order(1, 1), sent);
+    }
+
+    #[test]
+    fn packets_to_other_addresses_are_lost() {
+        let (a, mut b) = pair();
+
+        a.send_to(b"lost", "127.0.0.1:3".parse().unwrap());
+        a.send_to(b"kept", LOOPBACK_ADDRS[1]);
+
+        assert_eq!(b.receive(), Some((&b"kept"[..], LOOPBACK_ADDRS[0])));
+        assert_eq!(b.receive(), None);
+    }
+}
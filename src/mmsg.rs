@@ -0,0 +1,164 @@
+//! batched sending and receiving of packets using `recvmmsg` and `sendmmsg` on linux
+
+use std::{net::{UdpSocket, SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr}, os::fd::AsRawFd};
+
+
+/// buffers for receiving multiple packets with a single system call
+pub(crate) struct ReceiveBatch {
+    buffers: Vec<Vec<u8>>,
+
+    /// buffer index, length and origin of each packet received in the last batch
+    received: Vec<(usize, usize, SocketAddr)>,
+    /// index of the next packet in `received` to be returned
+    next: usize,
+}
+
+impl ReceiveBatch {
+    pub(crate) fn new(batch_size: usize, max_message_size: usize) -> Self {
+        ReceiveBatch {
+            buffers: vec![vec![0; max_message_size]; batch_size],
+
+            received: Vec::with_capacity(batch_size),
+            next: 0,
+        }
+    }
+
+    /// returns true if every packet from the last batch has been returned
+    pub(crate) fn is_empty(&self) -> bool {
+        self.next >= self.received.len()
+    }
+
+    /// returns the next packet from the last batch received
+    pub(crate) fn pop(&mut self) -> Option<(&[u8], SocketAddr)> {
+        let &(index, length, origin) = self.received.get(self.next)?;
+        self.next += 1;
+
+        Some((&self.buffers[index][..length], origin))
+    }
+
+    /// receives the next batch of packets without blocking, discarding any packets left from the last batch
+    ///
+    /// returns a `WouldBlock` error if there are no packets
+    pub(crate) fn fill(&mut self, socket: &UdpSocket) -> std::io::Result<()> {
+        self.received.clear();
+        self.next = 0;
+
+        let mut iovecs: Vec<libc::iovec> = self.buffers.iter_mut().map(|buffer| libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        }).collect();
+
+        // safety: sockaddr_storage is plain data and valid when zeroed
+        let mut addresses: Vec<libc::sockaddr_storage> = vec![unsafe { std::mem::zeroed() }; self.buffers.len()];
+
+        let mut headers: Vec<libc::mmsghdr> = iovecs.iter_mut().zip(addresses.iter_mut()).map(|(iovec, address)| {
+            // safety: msghdr is plain data and valid when zeroed
+            let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+            header.msg_name = address as *mut libc::sockaddr_storage as *mut libc::c_void;
+            header.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            header.msg_iov = iovec;
+            header.msg_iovlen = 1;
+
+            libc::mmsghdr {
+                msg_hdr: header,
+                msg_len: 0,
+            }
+        }).collect();
+
+        // safety: every header points to an address and a buffer that outlive the call
+        let received = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+
+        if received < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        for (index, header) in headers[..received as usize].iter().enumerate() {
+            // packets from address families other than ip are skipped
+            if let Some(origin) = to_socket_addr(header.msg_hdr.msg_name as *const libc::sockaddr_storage) {
+                self.received.push((index, header.msg_len as usize, origin));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+pub(crate) fn to_socket_addr(address: *const libc::sockaddr_storage) -> Option<SocketAddr> {
+    // safety: the address was written by the kernel and is cast based on it's family
+    unsafe {
+        match (*address).ss_family as libc::c_int {
+            libc::AF_INET => {
+                let address = &*(address as *const libc::sockaddr_in);
+
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)),
+                    u16::from_be(address.sin_port),
+                )))
+            },
+            libc::AF_INET6 => {
+                let address = &*(address as *const libc::sockaddr_in6);
+
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(address.sin6_addr.s6_addr),
+                    u16::from_be(address.sin6_port),
+                    address.sin6_flowinfo,
+                    address.sin6_scope_id,
+                )))
+            },
+            _ => None,
+        }
+    }
+}
+
+
+/// sends as many of the packets as possible with a single system call
+///
+/// returns the number of packets sent, which may be less than the number given
+pub(crate) fn send_batch(socket: &UdpSocket, datagrams: &[(Vec<u8>, SocketAddr)]) -> std::io::Result<usize> {
+    let addresses: Vec<socket2::SockAddr> = datagrams.iter().map(|&(_, addr)| addr.into()).collect();
+
+    let mut iovecs: Vec<libc::iovec> = datagrams.iter().map(|(datagram, _)| libc::iovec {
+        // the kernel doesn't write to the buffers when sending
+        iov_base: datagram.as_ptr() as *mut libc::c_void,
+        iov_len: datagram.len(),
+    }).collect();
+
+    let mut headers: Vec<libc::mmsghdr> = iovecs.iter_mut().zip(addresses.iter()).map(|(iovec, address)| {
+        // safety: msghdr is plain data and valid when zeroed
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        header.msg_name = address.as_ptr() as *mut libc::c_void;
+        header.msg_namelen = address.len();
+        header.msg_iov = iovec;
+        header.msg_iovlen = 1;
+
+        libc::mmsghdr {
+            msg_hdr: header,
+            msg_len: 0,
+        }
+    }).collect();
+
+    // safety: every header points to an address and a buffer that outlive the call
+    let sent = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            headers.len() as libc::c_uint,
+            0,
+        )
+    };
+
+    if sent < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(sent as usize)
+}
@@ -0,0 +1,686 @@
+//! thin wrappers around `Endpoint` with methods suited to servers and clients
+
+use std::{net::{SocketAddr, UdpSocket}, ops::ControlFlow, time::Duration, io::Read, sync::mpsc::{self, Sender, Receiver}, thread::JoinHandle, panic::AssertUnwindSafe};
+
+use crate::{Endpoint, ClientConfig, ChannelConfig, ChannelDebug, Diagnostics, Error, Event, MessageHandle, Metrics, RequestId, handle_events};
+
+
+/// an endpoint that accepts connections from many clients
+///
+/// each client connection holds a context of type `C`, see `Server::with_context`
+pub struct Server<C = ()> {
+    endpoint: Endpoint<C>,
+}
+
+impl Server {
+    /// binds a server to an address, `ClientConfig::listen` is always set to true
+    pub fn bind(mut config: ClientConfig, bind_addr: SocketAddr) -> Result<Self, Error> {
+        config.listen = true;
+
+        Ok(Server {
+            endpoint: Endpoint::bind(config, bind_addr)?,
+        })
+    }
+
+    /// creates a server from an already bound socket, see `Endpoint::from_socket`
+    pub fn from_socket(mut config: ClientConfig, socket: UdpSocket) -> Result<Self, Error> {
+        config.listen = true;
+
+        Ok(Server {
+            endpoint: Endpoint::from_socket(config, socket)?,
+        })
+    }
+}
+
+impl<C: Default> Server<C> {
+    /// changes the type of context held for each client, see `Endpoint::with_context`
+    pub fn with_context<D: Default>(self) -> Server<D> {
+        Server {
+            endpoint: self.endpoint.with_context(),
+        }
+    }
+
+    pub fn update(&mut self) -> Result<Vec<Event>, Error> {
+        self.endpoint.update()
+    }
+
+    /// see `Endpoint::update_bounded`
+    pub fn update_bounded(&mut self, budget: Duration) -> Result<Vec<Event>, Error> {
+        self.endpoint.update_bounded(budget)
+    }
+
+    /// see `Endpoint::receive_events`
+    pub fn receive_events(&mut self) -> Result<Vec<Event>, Error> {
+        self.endpoint.receive_events()
+    }
+
+    /// see `Endpoint::maintain`
+    pub fn maintain(&mut self) -> Result<Vec<Event>, Error> {
+        self.endpoint.maintain()
+    }
+
+    /// updates the server and handles each event in turn, see `Endpoint::for_each_event`
+    pub fn for_each_event(&mut self, f: impl FnMut(&mut Server<C>, Event) -> ControlFlow<()>) -> Result<(), Error> {
+        handle_events(self, |server| &mut server.endpoint, f)
+    }
+
+    pub fn send(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<Option<MessageHandle>, Error> {
+        self.endpoint.send(addr, channel_id, message)
+    }
+
+    pub fn send_str(&mut self, addr: SocketAddr, channel_id: u8, message: &str) -> Result<Option<MessageHandle>, Error> {
+        self.endpoint.send_str(addr, channel_id, message)
+    }
+
+    /// see `Endpoint::send_multi`
+    pub fn send_multi(&mut self, addr: SocketAddr, channel_ids: &[u8], message: &[u8]) -> Result<Vec<Option<MessageHandle>>, Error> {
+        self.endpoint.send_multi(addr, channel_ids, message)
+    }
+
+    /// see `Endpoint::send_stream`
+    pub fn send_stream(&mut self, addr: SocketAddr, channel_id: u8, reader: impl Read) -> Result<u32, Error> {
+        self.endpoint.send_stream(addr, channel_id, reader)
+    }
+
+    /// see `Endpoint::request`
+    pub fn request(&mut self, addr: SocketAddr, channel_id: u8, message: &[u8]) -> Result<RequestId, Error> {
+        self.endpoint.request(addr, channel_id, message)
+    }
+
+    /// see `Endpoint::respond`
+    pub fn respond(&mut self, request_id: RequestId, channel_id: u8, message: &[u8]) -> Result<(), Error> {
+        self.endpoint.respond(request_id, channel_id, message)
+    }
+
+    /// see `Endpoint::send_raw`
+    pub fn send_raw(&mut self, addr: SocketAddr, bytes: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_raw(addr, bytes)
+    }
+
+    /// see `Endpoint::send_broadcast`
+    pub fn send_broadcast(&mut self, port: u16, payload: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_broadcast(port, payload)
+    }
+
+    /// answers a discovery probe, see `Endpoint::send_discovery`
+    pub fn send_discovery(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_discovery(addr, payload)
+    }
+
+    /// sends a message to every client, see `Endpoint::broadcast`
+    pub fn broadcast(&mut self, channel_id: u8, message: &[u8]) -> Vec<(SocketAddr, Result<Option<MessageHandle>, Error>)> {
+        self.endpoint.broadcast(channel_id, message)
+    }
+
+    pub fn disconnect(&mut self, addr: SocketAddr) -> Result<bool, Error> {
+        self.endpoint.disconnect(addr)
+    }
+
+    /// see `Endpoint::drop_connection`
+    pub fn drop_connection(&mut self, addr: SocketAddr) -> bool {
+        self.endpoint.drop_connection(addr)
+    }
+
+    /// see `Endpoint::pause_connection`
+    pub fn pause_connection(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        self.endpoint.pause_connection(addr)
+    }
+
+    /// see `Endpoint::resume_connection`
+    pub fn resume_connection(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        self.endpoint.resume_connection(addr)
+    }
+
+    pub fn disconnect_all(&mut self) -> Vec<(SocketAddr, Result<(), Error>)> {
+        self.endpoint.disconnect_all()
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.endpoint.connections()
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.endpoint.connection_count()
+    }
+
+    /// see `Endpoint::connections_by_ping`
+    pub fn connections_by_ping(&self) -> Vec<(SocketAddr, Option<u128>)> {
+        self.endpoint.connections_by_ping()
+    }
+
+    /// see `Endpoint::connections_by_last_seen`
+    pub fn connections_by_last_seen(&self) -> Vec<(SocketAddr, u128)> {
+        self.endpoint.connections_by_last_seen()
+    }
+
+    /// see `Endpoint::take_diagnostics`
+    pub fn take_diagnostics(&mut self) -> Diagnostics {
+        self.endpoint.take_diagnostics()
+    }
+
+    /// see `Endpoint::metrics_snapshot`
+    pub fn metrics_snapshot(&self) -> Metrics {
+        self.endpoint.metrics_snapshot()
+    }
+
+    /// see `Endpoint::inject_raw`
+    #[cfg(any(test, feature = "testing"))]
+    pub fn inject_raw(&mut self, from: SocketAddr, bytes: &[u8]) {
+        self.endpoint.inject_raw(from, bytes)
+    }
+
+    /// see `Endpoint::add_channel`
+    pub fn add_channel(&mut self, channel_config: ChannelConfig) -> Result<u8, Error> {
+        self.endpoint.add_channel(channel_config)
+    }
+
+    /// sets a filter for which clients to accept, see `Endpoint::set_accept_filter`
+    pub fn set_accept_filter(&mut self, accept_filter: impl FnMut(SocketAddr) -> bool + Send + 'static) {
+        self.endpoint.set_accept_filter(accept_filter);
+    }
+
+    pub fn get_ping(&self, addr: SocketAddr) -> Result<Option<u128>, Error> {
+        self.endpoint.get_ping(addr)
+    }
+
+    /// see `Endpoint::set_heartbeat_payload`
+    pub fn set_heartbeat_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        self.endpoint.set_heartbeat_payload(payload)
+    }
+
+    /// see `Endpoint::flush_acks`
+    pub fn flush_acks(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        self.endpoint.flush_acks(addr)
+    }
+
+    /// see `Endpoint::outbound_queue_len`
+    pub fn outbound_queue_len(&self, addr: SocketAddr) -> Result<usize, Error> {
+        self.endpoint.outbound_queue_len(addr)
+    }
+
+    /// see `Endpoint::debug_channel_state`
+    pub fn debug_channel_state(&self, addr: SocketAddr, channel_id: u8) -> Option<ChannelDebug> {
+        self.endpoint.debug_channel_state(addr, channel_id)
+    }
+
+    /// see `Endpoint::next_retransmit`
+    pub fn next_retransmit(&self, addr: SocketAddr, channel_id: u8) -> Option<Duration> {
+        self.endpoint.next_retransmit(addr, channel_id)
+    }
+
+    /// see `Endpoint::resend_interval`
+    pub fn resend_interval(&self, addr: SocketAddr, channel_id: u8) -> Option<Duration> {
+        self.endpoint.resend_interval(addr, channel_id)
+    }
+
+    pub fn context(&self, addr: SocketAddr) -> Option<&C> {
+        self.endpoint.context(addr)
+    }
+
+    pub fn context_mut(&mut self, addr: SocketAddr) -> Option<&mut C> {
+        self.endpoint.context_mut(addr)
+    }
+
+    pub fn bound_addr(&self) -> Result<SocketAddr, Error> {
+        self.endpoint.bound_addr()
+    }
+
+    /// gets the underlying endpoint for anything not exposed by the server
+    pub fn endpoint(&self) -> &Endpoint<C> {
+        &self.endpoint
+    }
+
+    pub fn endpoint_mut(&mut self) -> &mut Endpoint<C> {
+        &mut self.endpoint
+    }
+
+    pub fn into_endpoint(self) -> Endpoint<C> {
+        self.endpoint
+    }
+}
+
+
+/// an endpoint with a single connection to a server
+///
+/// the connection holds a context of type `C`, see `Client::with_context`
+pub struct Client<C = ()> {
+    endpoint: Endpoint<C>,
+
+    server_addr: SocketAddr,
+}
+
+impl Client {
+    /// binds a client to an address and connects to a server, `ClientConfig::listen` is always set to false
+    pub fn bind(mut config: ClientConfig, bind_addr: SocketAddr, server_addr: SocketAddr) -> Result<Self, Error> {
+        config.listen = false;
+
+        Client::connect_endpoint(Endpoint::bind(config, bind_addr)?, server_addr)
+    }
+
+    /// binds a client to any address and connects to a server, `ClientConfig::listen` is always set to false
+    pub fn bind_any(mut config: ClientConfig, server_addr: SocketAddr) -> Result<Self, Error> {
+        config.listen = false;
+
+        Client::connect_endpoint(Endpoint::bind_any(config)?, server_addr)
+    }
+
+    /// creates a client connected to a server in process, without any sockets, see `Endpoint::loopback_pair`
+    ///
+    /// `ClientConfig::listen` is set to false for the client and true for the server
+    pub fn loopback(mut config: ClientConfig, mut server_config: ClientConfig) -> Result<(Self, Server), Error> {
+        config.listen = false;
+        server_config.listen = true;
+
+        let (endpoint, server_endpoint) = Endpoint::loopback_pair(config, server_config)?;
+        let server_addr = server_endpoint.bound_addr()?;
+
+        Ok((
+            Client::connect_endpoint(endpoint, server_addr)?,
+            Server {
+                endpoint: server_endpoint,
+            },
+        ))
+    }
+
+    /// the same as `Client::loopback` but with packets reordered, see `Endpoint::loopback_pair_reordered`
+    pub fn loopback_reordered(mut config: ClientConfig, mut server_config: ClientConfig, window: usize, seed: u64) -> Result<(Self, Server), Error> {
+        config.listen = false;
+        server_config.listen = true;
+
+        let (endpoint, server_endpoint) = Endpoint::loopback_pair_reordered(config, server_config, window, seed)?;
+        let server_addr = server_endpoint.bound_addr()?;
+
+        Ok((
+            Client::connect_endpoint(endpoint, server_addr)?,
+            Server {
+                endpoint: server_endpoint,
+            },
+        ))
+    }
+
+    fn connect_endpoint(mut endpoint: Endpoint, server_addr: SocketAddr) -> Result<Self, Error> {
+        endpoint.connect(server_addr)?;
+
+        Ok(Client {
+            endpoint,
+
+            server_addr,
+        })
+    }
+}
+
+impl<C: Default> Client<C> {
+    /// changes the type of context held for the connection, see `Endpoint::with_context`
+    pub fn with_context<D: Default>(self) -> Client<D> {
+        Client {
+            endpoint: self.endpoint.with_context(),
+
+            server_addr: self.server_addr,
+        }
+    }
+
+    pub fn context(&self) -> Option<&C> {
+        self.endpoint.context(self.server_addr)
+    }
+
+    pub fn context_mut(&mut self) -> Option<&mut C> {
+        self.endpoint.context_mut(self.server_addr)
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    /// returns false once the connection to the server has been lost or closed
+    pub fn is_connected(&self) -> bool {
+        self.endpoint.connections().next().is_some()
+    }
+
+    pub fn update(&mut self) -> Result<Vec<Event>, Error> {
+        self.endpoint.update()
+    }
+
+    /// see `Endpoint::update_bounded`
+    pub fn update_bounded(&mut self, budget: Duration) -> Result<Vec<Event>, Error> {
+        self.endpoint.update_bounded(budget)
+    }
+
+    /// see `Endpoint::receive_events`
+    pub fn receive_events(&mut self) -> Result<Vec<Event>, Error> {
+        self.endpoint.receive_events()
+    }
+
+    /// see `Endpoint::maintain`
+    pub fn maintain(&mut self) -> Result<Vec<Event>, Error> {
+        self.endpoint.maintain()
+    }
+
+    /// waits for the server to echo back a heartbeat, see `Endpoint::wait_connected`
+    pub fn wait_connected(&mut self, timeout: Duration) -> Result<bool, Error> {
+        self.endpoint.wait_connected(self.server_addr, timeout)
+    }
+
+    /// updates the client and handles each event in turn, see `Endpoint::for_each_event`
+    pub fn for_each_event(&mut self, f: impl FnMut(&mut Client<C>, Event) -> ControlFlow<()>) -> Result<(), Error> {
+        handle_events(self, |client| &mut client.endpoint, f)
+    }
+
+    /// sends a message to the server
+    pub fn send(&mut self, channel_id: u8, message: &[u8]) -> Result<Option<MessageHandle>, Error> {
+        self.endpoint.send(self.server_addr, channel_id, message)
+    }
+
+    /// sends a string to the server
+    pub fn send_str(&mut self, channel_id: u8, message: &str) -> Result<Option<MessageHandle>, Error> {
+        self.endpoint.send_str(self.server_addr, channel_id, message)
+    }
+
+    /// sends a message to the server on several channels, see `Endpoint::send_multi`
+    pub fn send_multi(&mut self, channel_ids: &[u8], message: &[u8]) -> Result<Vec<Option<MessageHandle>>, Error> {
+        self.endpoint.send_multi(self.server_addr, channel_ids, message)
+    }
+
+    /// sends everything read from `reader` to the server, see `Endpoint::send_stream`
+    pub fn send_stream(&mut self, channel_id: u8, reader: impl Read) -> Result<u32, Error> {
+        self.endpoint.send_stream(self.server_addr, channel_id, reader)
+    }
+
+    /// sends a request to the server, see `Endpoint::request`
+    pub fn request(&mut self, channel_id: u8, message: &[u8]) -> Result<RequestId, Error> {
+        self.endpoint.request(self.server_addr, channel_id, message)
+    }
+
+    /// answers a request from the server, see `Endpoint::respond`
+    pub fn respond(&mut self, request_id: RequestId, channel_id: u8, message: &[u8]) -> Result<(), Error> {
+        self.endpoint.respond(request_id, channel_id, message)
+    }
+
+    /// sends bytes to any address, not only the server, see `Endpoint::send_raw`
+    pub fn send_raw(&mut self, addr: SocketAddr, bytes: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_raw(addr, bytes)
+    }
+
+    /// looks for servers on the local network listening on the port, see `Endpoint::send_broadcast`
+    pub fn send_broadcast(&mut self, port: u16, payload: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_broadcast(port, payload)
+    }
+
+    /// see `Endpoint::send_discovery`
+    pub fn send_discovery(&mut self, addr: SocketAddr, payload: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_discovery(addr, payload)
+    }
+
+    /// gets the ping to the server, see `Endpoint::get_ping`
+    pub fn ping(&self) -> Result<Option<u128>, Error> {
+        self.endpoint.get_ping(self.server_addr)
+    }
+
+    /// sends a message, reconnecting to the server first if the connection was lost, see `Endpoint::send_or_connect`
+    pub fn send_or_connect(&mut self, channel_id: u8, message: &[u8]) -> Result<(), Error> {
+        self.endpoint.send_or_connect(self.server_addr, channel_id, message)
+    }
+
+    /// see `Endpoint::add_channel`
+    pub fn add_channel(&mut self, channel_config: ChannelConfig) -> Result<u8, Error> {
+        self.endpoint.add_channel(channel_config)
+    }
+
+    /// sends a ping to the server, see `Endpoint::send_ping`
+    pub fn send_ping(&mut self) -> Result<(), Error> {
+        self.endpoint.send_ping(self.server_addr)
+    }
+
+    /// closes the connection to the server
+    pub fn disconnect(&mut self) -> Result<bool, Error> {
+        self.endpoint.disconnect(self.server_addr)
+    }
+
+    /// forgets the server without telling it, see `Endpoint::drop_connection`
+    pub fn drop_connection(&mut self) -> bool {
+        self.endpoint.drop_connection(self.server_addr)
+    }
+
+    /// pauses the connection to the server, like while the app is in the background, see `Endpoint::pause_connection`
+    pub fn pause_connection(&mut self) -> Result<(), Error> {
+        self.endpoint.pause_connection(self.server_addr)
+    }
+
+    /// see `Endpoint::resume_connection`
+    pub fn resume_connection(&mut self) -> Result<(), Error> {
+        self.endpoint.resume_connection(self.server_addr)
+    }
+
+    /// see `Endpoint::set_heartbeat_payload`
+    pub fn set_heartbeat_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        self.endpoint.set_heartbeat_payload(payload)
+    }
+
+    /// see `Endpoint::flush_acks`
+    pub fn flush_acks(&mut self) -> Result<(), Error> {
+        self.endpoint.flush_acks(self.server_addr)
+    }
+
+    /// see `Endpoint::outbound_queue_len`
+    pub fn outbound_queue_len(&self) -> Result<usize, Error> {
+        self.endpoint.outbound_queue_len(self.server_addr)
+    }
+
+    /// see `Endpoint::debug_channel_state`
+    pub fn debug_channel_state(&self, channel_id: u8) -> Option<ChannelDebug> {
+        self.endpoint.debug_channel_state(self.server_addr, channel_id)
+    }
+
+    /// see `Endpoint::next_retransmit`
+    pub fn next_retransmit(&self, channel_id: u8) -> Option<Duration> {
+        self.endpoint.next_retransmit(self.server_addr, channel_id)
+    }
+
+    /// see `Endpoint::resend_interval`
+    pub fn resend_interval(&self, channel_id: u8) -> Option<Duration> {
+        self.endpoint.resend_interval(self.server_addr, channel_id)
+    }
+
+    pub fn bound_addr(&self) -> Result<SocketAddr, Error> {
+        self.endpoint.bound_addr()
+    }
+
+    pub fn local_port(&self) -> Result<u16, Error> {
+        self.endpoint.local_port()
+    }
+
+    pub fn is_wildcard_bound(&self) -> bool {
+        self.endpoint.is_wildcard_bound()
+    }
+
+    /// see `Endpoint::take_diagnostics`
+    pub fn take_diagnostics(&mut self) -> Diagnostics {
+        self.endpoint.take_diagnostics()
+    }
+
+    /// see `Endpoint::metrics_snapshot`
+    pub fn metrics_snapshot(&self) -> Metrics {
+        self.endpoint.metrics_snapshot()
+    }
+
+    /// see `Endpoint::inject_raw`
+    #[cfg(any(test, feature = "testing"))]
+    pub fn inject_raw(&mut self, from: SocketAddr, bytes: &[u8]) {
+        self.endpoint.inject_raw(from, bytes)
+    }
+
+    /// gets the underlying endpoint for anything not exposed by the client
+    pub fn endpoint(&self) -> &Endpoint<C> {
+        &self.endpoint
+    }
+
+    pub fn endpoint_mut(&mut self) -> &mut Endpoint<C> {
+        &mut self.endpoint
+    }
+
+    pub fn into_endpoint(self) -> Endpoint<C> {
+        self.endpoint
+    }
+}
+
+
+/// many clients driven together from a few threads, like for a load generator simulating thousands of players
+///
+/// each client still has it's own socket, the pool only shares the threads their updates run on,
+/// which are started once with the pool and live until it's dropped
+pub struct ClientPool<C = ()> {
+    /// the clients dealt out round robin, so client `index` is at `index / chunks.len()` in chunk `index % chunks.len()`
+    chunks: Vec<Vec<Client<C>>>,
+
+    /// one per chunk, none if the pool updates on the calling thread
+    workers: Vec<Worker<C>>,
+}
+
+/// a chunk of clients handed back by a worker with the result of updating each one, or the panic from updating them
+type UpdatedChunk<C> = (Vec<Client<C>>, std::thread::Result<Vec<Result<Vec<Event>, Error>>>);
+
+/// a thread that updates each chunk of clients it's handed and hands it back with the results
+struct Worker<C> {
+    chunks: Sender<Vec<Client<C>>>,
+    updated: Receiver<UpdatedChunk<C>>,
+
+    thread: JoinHandle<()>,
+}
+
+impl<C: Default + Send + 'static> Worker<C> {
+    fn spawn() -> Self {
+        let (chunks, chunk_receiver) = mpsc::channel::<Vec<Client<C>>>();
+        let (updated_sender, updated) = mpsc::channel();
+
+        let thread = std::thread::spawn(move || {
+            // ends once the pool drops it's sender
+            for mut chunk in chunk_receiver {
+                // a panicking client still hands the chunk back, so the pool keeps every client
+                let results = std::panic::catch_unwind(AssertUnwindSafe(|| chunk.iter_mut().map(Client::update).collect()));
+                if updated_sender.send((chunk, results)).is_err() {break;}
+            }
+        });
+
+        Worker {
+            chunks,
+            updated,
+
+            thread,
+        }
+    }
+}
+
+impl<C: Default + Send + 'static> ClientPool<C> {
+    /// creates an empty pool that updates it's clients over a number of threads, at least 1
+    ///
+    /// with 1 thread the clients are updated on the calling thread and no threads are started
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+
+        ClientPool {
+            chunks: (0..threads).map(|_| Vec::new()).collect(),
+
+            workers: if threads == 1 {Vec::new()} else {(0..threads).map(|_| Worker::spawn()).collect()},
+        }
+    }
+
+    /// adds a client to the pool, returning it's index
+    pub fn push(&mut self, client: Client<C>) -> usize {
+        let index = self.len();
+        let chunks = self.chunks.len();
+
+        self.chunks[index % chunks].push(client);
+        index
+    }
+
+    /// updates every client, returning the result for each client by index, in order
+    ///
+    /// every thread's chunk is updated at once, and the call returns once they've all been updated,
+    /// if a client panics the panic is passed on only after every chunk is back in the pool
+    pub fn update(&mut self) -> Vec<(usize, Result<Vec<Event>, Error>)> {
+        if self.workers.is_empty() {
+            return self.chunks[0].iter_mut().map(Client::update).enumerate().collect();
+        }
+
+        // workers only stop once the pool is dropped, so every chunk sent comes back
+        for (worker, chunk) in self.workers.iter().zip(self.chunks.iter_mut()) {
+            worker.chunks.send(std::mem::take(chunk)).expect("client pool worker stopped");
+        }
+
+        let chunks = self.chunks.len();
+        let mut results = Vec::new();
+        let mut panic = None;
+
+        // every chunk is put back before a panic is passed on, so the pool is left whole if it's caught
+        for (chunk_index, (worker, chunk)) in self.workers.iter().zip(self.chunks.iter_mut()).enumerate() {
+            let (clients, chunk_results) = worker.updated.recv().expect("client pool worker stopped");
+            *chunk = clients;
+
+            match chunk_results {
+                Ok(chunk_results) => results.extend(chunk_results.into_iter().enumerate().map(|(position, result)| (position * chunks + chunk_index, result))),
+                Err(chunk_panic) => {panic.get_or_insert(chunk_panic);},
+            }
+        }
+
+        if let Some(panic) = panic {
+            std::panic::resume_unwind(panic);
+        }
+
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results
+    }
+
+    pub fn client(&self, index: usize) -> Option<&Client<C>> {
+        self.chunks[index % self.chunks.len()].get(index / self.chunks.len())
+    }
+
+    pub fn client_mut(&mut self, index: usize) -> Option<&mut Client<C>> {
+        let chunks = self.chunks.len();
+        self.chunks[index % chunks].get_mut(index / chunks)
+    }
+
+    /// every client in order of index
+    pub fn clients(&self) -> impl Iterator<Item = &Client<C>> {
+        let mut chunks: Vec<_> = self.chunks.iter().map(|chunk| chunk.iter()).collect();
+        (0..self.len()).filter_map(move |index| {
+            let chunk_count = chunks.len();
+            chunks[index % chunk_count].next()
+        })
+    }
+
+    /// every client in order of index
+    pub fn clients_mut(&mut self) -> impl Iterator<Item = &mut Client<C>> {
+        let len = self.len();
+        let mut chunks: Vec<_> = self.chunks.iter_mut().map(|chunk| chunk.iter_mut()).collect();
+        (0..len).filter_map(move |index| {
+            let chunk_count = chunks.len();
+            chunks[index % chunk_count].next()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.iter().all(Vec::is_empty)
+    }
+
+    /// takes every client out of the pool in order of index
+    pub fn into_clients(mut self) -> Vec<Client<C>> {
+        let len = self.len();
+        let mut chunks: Vec<_> = std::mem::take(&mut self.chunks).into_iter().map(Vec::into_iter).collect();
+        let chunk_count = chunks.len();
+
+        (0..len).filter_map(|index| chunks[index % chunk_count].next()).collect()
+    }
+}
+
+impl<C> Drop for ClientPool<C> {
+    fn drop(&mut self) {
+        // dropping a worker's sender ends it's loop, then it's joined so no thread outlives the pool
+        for Worker { chunks, thread, .. } in std::mem::take(&mut self.workers) {
+            drop(chunks);
+            let _ = thread.join();
+        }
+    }
+}
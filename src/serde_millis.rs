@@ -0,0 +1,28 @@
+//! serializing the millisecond times in configs as u64s, as they're u128s which formats like toml can't represent
+//!
+//! used with `#[serde(with = "serde_millis")]` when the `serde` feature is enabled
+
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+
+/// times too long for a u64 are saturated, which is still over 500 million years
+pub(crate) fn serialize<S: Serializer>(millis: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+    u64::try_from(*millis).unwrap_or(u64::MAX).serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+    u64::deserialize(deserializer).map(u128::from)
+}
+
+/// the same for optional times
+pub(crate) mod option {
+    use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+    pub(crate) fn serialize<S: Serializer>(millis: &Option<u128>, serializer: S) -> Result<S::Ok, S::Error> {
+        millis.map(|millis| u64::try_from(millis).unwrap_or(u64::MAX)).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+        Option::<u64>::deserialize(deserializer).map(|millis| millis.map(u128::from))
+    }
+}
@@ -0,0 +1,508 @@
+//! the framing of packets on reliable and fec channels, following the channel id
+//!
+//! every number is big endian
+
+/// every message in a packet on a batched unreliable channel is prefixed by it's length as a u16
+pub(crate) const BATCHED_PREFIX_LEN: usize = 2;
+
+pub(crate) fn batched_prefix(message: &[u8]) -> [u8; BATCHED_PREFIX_LEN] {
+    (message.len() as u16).to_be_bytes()
+}
+
+/// splits a packet from a batched unreliable channel into it's messages, a truncated message at the end is dropped
+pub(crate) fn split_batched(mut packet: &[u8]) -> Vec<&[u8]> {
+    let mut messages = Vec::new();
+
+    while let Some((len, rest)) = packet.split_first_chunk::<BATCHED_PREFIX_LEN>() {
+        let Some((message, rest)) = rest.split_at_checked(u16::from_be_bytes(*len) as usize) else {break;};
+
+        messages.push(message);
+        packet = rest;
+    }
+
+    messages
+}
+
+
+/// every message on an unreliable sequenced channel is prefixed by it's sequence number as a u64
+pub(crate) const SEQUENCED_PREFIX_LEN: usize = 8;
+
+
+/// appended to the end of every packet when `ClientConfig::checksum` is set
+pub(crate) const CHECKSUM_LEN: usize = 4;
+
+/// lookup table for the crc32 used by ethernet and zip
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {(crc >> 1) ^ 0xEDB88320} else {crc >> 1};
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+pub(crate) fn checksum(packet: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let crc = packet.iter().fold(!0u32, |crc, byte| CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8));
+
+    (!crc).to_be_bytes()
+}
+
+/// splits the checksum off the end of a packet, `None` if it's missing or doesn't match
+pub(crate) fn verify_checksum(packet: &[u8]) -> Option<&[u8]> {
+    let (packet, expected) = packet.split_last_chunk::<CHECKSUM_LEN>()?;
+
+    (checksum(packet) == *expected).then_some(packet)
+}
+
+
+/// the start of every packet sent on a reliable channel, followed by the message or a fragment of it
+///
+/// after the sequence is a kind byte, with the lowest bit set if there's a fragment, the next bit set if there's an ack
+/// and the one after set if there's a skip
+pub(crate) struct ReliableHeader {
+    pub(crate) seq: u64,
+    /// set when a message too long for one packet has been split over consecutive sequences
+    pub(crate) fragment: Option<Fragment>,
+    /// acks for a send reliable channel of the receiver, see `ClientConfig::piggyback_acks`
+    pub(crate) ack: Option<PiggybackedAck>,
+    /// every sequence before this has been acknowledged or given up on by the sender,
+    /// set once a message on a channel with a lifetime has expired so the receiver can move past it
+    pub(crate) skip: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Fragment {
+    /// the first fragment of a message is at index 0, so the message's sequence is `seq - index`
+    pub(crate) index: u16,
+    pub(crate) count: u16,
+}
+
+/// acks carried by a packet on another channel, for the channel with the id
+pub(crate) struct PiggybackedAck {
+    pub(crate) channel_id: u8,
+    pub(crate) ack: ReliableAck,
+}
+
+impl PiggybackedAck {
+    pub(crate) const LEN: usize = 1 + ReliableAck::LEN;
+}
+
+impl ReliableHeader {
+    /// the length of a header for a whole message
+    pub(crate) const MIN_LEN: usize = 9;
+    /// the length of a header for a fragment, not counting any piggybacked ack or skip
+    pub(crate) const MAX_LEN: usize = 13;
+    /// the length added by a skip
+    pub(crate) const SKIP_LEN: usize = 8;
+
+    const FRAGMENT_BIT: u8 = 1;
+    const ACK_BIT: u8 = 2;
+    const SKIP_BIT: u8 = 4;
+
+    /// the length of the header when encoded
+    pub(crate) fn len(&self) -> usize {
+        Self::MIN_LEN
+            + self.fragment.map_or(0, |_| Self::MAX_LEN - Self::MIN_LEN)
+            + self.ack.as_ref().map_or(0, |_| PiggybackedAck::LEN)
+            + self.skip.map_or(0, |_| Self::SKIP_LEN)
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+
+        let fragment_bit = if self.fragment.is_some() {Self::FRAGMENT_BIT} else {0};
+        let ack_bit = if self.ack.is_some() {Self::ACK_BIT} else {0};
+        let skip_bit = if self.skip.is_some() {Self::SKIP_BIT} else {0};
+        bytes.push(fragment_bit | ack_bit | skip_bit);
+
+        if let Some(Fragment { index, count }) = self.fragment {
+            bytes.extend_from_slice(&index.to_be_bytes());
+            bytes.extend_from_slice(&count.to_be_bytes());
+        }
+
+        if let Some(PiggybackedAck { channel_id, ack }) = &self.ack {
+            bytes.push(*channel_id);
+            bytes.extend_from_slice(&ack.encode());
+        }
+
+        if let Some(skip) = self.skip {
+            bytes.extend_from_slice(&skip.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// returns the header and the message after it, `None` if the packet is too short or the kind is unknown
+    pub(crate) fn decode(packet: &[u8]) -> Option<(Self, &[u8])> {
+        let (seq, rest) = packet.split_first_chunk::<8>()?;
+        let (kind, mut rest) = rest.split_first()?;
+
+        if kind & !(Self::FRAGMENT_BIT | Self::ACK_BIT | Self::SKIP_BIT) != 0 {
+            return None;
+        }
+
+        let mut fragment = None;
+        if kind & Self::FRAGMENT_BIT != 0 {
+            let (index, after) = rest.split_first_chunk::<2>()?;
+            let (count, after) = after.split_first_chunk::<2>()?;
+
+            fragment = Some(Fragment { index: u16::from_be_bytes(*index), count: u16::from_be_bytes(*count) });
+            rest = after;
+        }
+
+        let mut ack = None;
+        if kind & Self::ACK_BIT != 0 {
+            let (channel_id, after) = rest.split_first()?;
+            let (start_seq, after) = after.split_first_chunk::<8>()?;
+            let (mask, after) = after.split_first_chunk::<8>()?;
+
+            ack = Some(PiggybackedAck {
+                channel_id: *channel_id,
+                ack: ReliableAck { start_seq: u64::from_be_bytes(*start_seq), mask: u64::from_be_bytes(*mask) },
+            });
+            rest = after;
+        }
+
+        let mut skip = None;
+        if kind & Self::SKIP_BIT != 0 {
+            let (skip_seq, after) = rest.split_first_chunk::<8>()?;
+
+            skip = Some(u64::from_be_bytes(*skip_seq));
+            rest = after;
+        }
+
+        Some((ReliableHeader { seq: u64::from_be_bytes(*seq), fragment, ack, skip }, rest))
+    }
+}
+
+
+/// acknowledges a start sequence and any of the 64 sequences after it with a set bit in the mask
+pub(crate) struct ReliableAck {
+    pub(crate) start_seq: u64,
+    /// bit `i` acknowledges `start_seq + 1 + i`
+    pub(crate) mask: u64,
+}
+
+impl ReliableAck {
+    pub(crate) const LEN: usize = 16;
+    /// the length of an ack without a mask
+    pub(crate) const MIN_LEN: usize = 8;
+
+    pub(crate) fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..8].copy_from_slice(&self.start_seq.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.mask.to_be_bytes());
+        bytes
+    }
+
+    /// the mask can be left off to only acknowledge the start sequence
+    pub(crate) fn decode(packet: &[u8]) -> Option<Self> {
+        let (start_seq, rest) = packet.split_first_chunk::<8>()?;
+        let mask = rest.first_chunk::<8>().map_or(0, |mask| u64::from_be_bytes(*mask));
+
+        Some(ReliableAck {
+            start_seq: u64::from_be_bytes(*start_seq),
+            mask,
+        })
+    }
+
+    /// every sequence acknowledged
+    pub(crate) fn acked(&self) -> impl Iterator<Item = u64> + '_ {
+        let acked = (0..u64::BITS as u64).filter(|bit| self.mask & (1 << bit) != 0).filter_map(|bit| self.start_seq.checked_add(bit + 1));
+
+        std::iter::once(self.start_seq).chain(acked)
+    }
+}
+
+
+/// the start of every packet sent on a fec channel, followed by an encoded symbol
+pub(crate) struct FecHeader {
+    pub(crate) seq: u64,
+    pub(crate) num_source_symbols: u32,
+    /// a single byte, which is why a message can have at most 255 symbols including repair symbols
+    pub(crate) symbol_index: u8,
+    /// length of the whole message
+    pub(crate) message_len: u16,
+}
+
+impl FecHeader {
+    pub(crate) const LEN: usize = 15;
+
+    pub(crate) fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..8].copy_from_slice(&self.seq.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.num_source_symbols.to_be_bytes());
+        bytes[12] = self.symbol_index;
+        bytes[13..].copy_from_slice(&self.message_len.to_be_bytes());
+        bytes
+    }
+
+    /// returns the header and the symbol after it, `None` if the packet is too short
+    pub(crate) fn decode(packet: &[u8]) -> Option<(Self, &[u8])> {
+        let (seq, rest) = packet.split_first_chunk::<8>()?;
+        let (num_source_symbols, rest) = rest.split_first_chunk::<4>()?;
+        let (symbol_index, rest) = rest.split_first()?;
+        let (message_len, symbol) = rest.split_first_chunk::<2>()?;
+
+        Some((FecHeader {
+            seq: u64::from_be_bytes(*seq),
+            num_source_symbols: u32::from_be_bytes(*num_source_symbols),
+            symbol_index: *symbol_index,
+            message_len: u16::from_be_bytes(*message_len),
+        }, symbol))
+    }
+}
+
+
+/// the start of every message sent by `Endpoint::send_stream`, inside the reliable framing
+pub(crate) struct StreamHeader {
+    pub(crate) stream_id: u32,
+    pub(crate) chunk_index: u32,
+    /// number of chunks the whole stream was split into
+    pub(crate) chunk_count: u32,
+}
+
+impl StreamHeader {
+    pub(crate) const LEN: usize = 12;
+
+    pub(crate) fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..4].copy_from_slice(&self.stream_id.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.chunk_index.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.chunk_count.to_be_bytes());
+        bytes
+    }
+
+    /// returns the header and the chunk after it, `None` if the message is too short
+    pub(crate) fn decode(message: &[u8]) -> Option<(Self, &[u8])> {
+        let (stream_id, rest) = message.split_first_chunk::<4>()?;
+        let (chunk_index, rest) = rest.split_first_chunk::<4>()?;
+        let (chunk_count, chunk) = rest.split_first_chunk::<4>()?;
+
+        Some((StreamHeader {
+            stream_id: u32::from_be_bytes(*stream_id),
+            chunk_index: u32::from_be_bytes(*chunk_index),
+            chunk_count: u32::from_be_bytes(*chunk_count),
+        }, chunk))
+    }
+}
+
+
+/// the start of every message sent by `Endpoint::request` and `Endpoint::respond`, inside the reliable framing
+pub(crate) struct RequestHeader {
+    /// the id the requester gave the request, echoed back in the response
+    pub(crate) id: u64,
+    pub(crate) response: bool,
+}
+
+impl RequestHeader {
+    pub(crate) const LEN: usize = 9;
+
+    pub(crate) fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = self.response as u8;
+        bytes[1..].copy_from_slice(&self.id.to_be_bytes());
+        bytes
+    }
+
+    /// returns the header and the payload after it, `None` if the message is too short or the kind is unknown
+    pub(crate) fn decode(message: &[u8]) -> Option<(Self, &[u8])> {
+        let (kind, rest) = message.split_first()?;
+        let (id, payload) = rest.split_first_chunk::<8>()?;
+
+        let response = match kind {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+
+        Some((RequestHeader { id: u64::from_be_bytes(*id), response }, payload))
+    }
+}
+
+
+/// sent back by a receiving fec channel
+pub(crate) enum FecAck {
+    /// the whole message has been received
+    Message {
+        seq: u64,
+    },
+    /// a single symbol has been received
+    Symbol {
+        seq: u64,
+        symbol_index: u8,
+    },
+}
+
+impl FecAck {
+    /// the length of the shortest ack
+    pub(crate) const MIN_LEN: usize = 9;
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(10);
+
+        match self {
+            FecAck::Message { seq } => {
+                bytes.push(0);
+                bytes.extend_from_slice(&seq.to_be_bytes());
+            },
+            FecAck::Symbol { seq, symbol_index } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&seq.to_be_bytes());
+                bytes.push(*symbol_index);
+            },
+        }
+
+        bytes
+    }
+
+    /// `None` if the ack type is unknown or the packet is too short
+    pub(crate) fn decode(packet: &[u8]) -> Option<Self> {
+        let (ack_type, rest) = packet.split_first()?;
+        let (seq, rest) = rest.split_first_chunk::<8>()?;
+        let seq = u64::from_be_bytes(*seq);
+
+        match ack_type {
+            0 => Some(FecAck::Message { seq }),
+            1 => Some(FecAck::Symbol { seq, symbol_index: *rest.first()? }),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reliable_header_round_trips() {
+        for kind in 0..8 {
+            let header = ReliableHeader {
+                seq: 0x0102030405060708,
+                fragment: (kind & 1 != 0).then_some(Fragment { index: 3, count: 7 }),
+                ack: (kind & 2 != 0).then_some(PiggybackedAck { channel_id: 5, ack: ReliableAck { start_seq: 40, mask: 0b101 } }),
+                skip: (kind & 4 != 0).then_some(99),
+            };
+
+            let mut packet = header.encode();
+            assert_eq!(packet.len(), header.len());
+            packet.extend_from_slice(b"message");
+
+            let (decoded, message) = ReliableHeader::decode(&packet).unwrap();
+            assert_eq!(message, b"message");
+            assert_eq!(decoded.seq, header.seq);
+            assert_eq!(decoded.fragment.map(|fragment| (fragment.index, fragment.count)), header.fragment.map(|fragment| (fragment.index, fragment.count)));
+            assert_eq!(decoded.ack.map(|ack| (ack.channel_id, ack.ack.start_seq, ack.ack.mask)), header.ack.as_ref().map(|ack| (ack.channel_id, ack.ack.start_seq, ack.ack.mask)));
+            assert_eq!(decoded.skip, header.skip);
+
+            // every field is needed
+            assert!(ReliableHeader::decode(&packet[..header.len() - 1]).is_none());
+        }
+
+        // unknown kind bits are rejected
+        let mut packet = ReliableHeader { seq: 1, fragment: None, ack: None, skip: None }.encode();
+        packet[8] = 8;
+        assert!(ReliableHeader::decode(&packet).is_none());
+    }
+
+    #[test]
+    fn reliable_ack_round_trips() {
+        let ack = ReliableAck { start_seq: 10, mask: 1 | 1 << 63 };
+        let decoded = ReliableAck::decode(&ack.encode()).unwrap();
+        assert_eq!(decoded.acked().collect::<Vec<_>>(), vec![10, 11, 74]);
+
+        // the mask can be left off
+        let decoded = ReliableAck::decode(&ack.encode()[..ReliableAck::MIN_LEN]).unwrap();
+        assert_eq!(decoded.acked().collect::<Vec<_>>(), vec![10]);
+
+        // sequences past the largest aren't acknowledged
+        let ack = ReliableAck { start_seq: u64::MAX, mask: !0 };
+        assert_eq!(ack.acked().collect::<Vec<_>>(), vec![u64::MAX]);
+    }
+
+    #[test]
+    fn fec_header_round_trips() {
+        let header = FecHeader { seq: 12, num_source_symbols: 4, symbol_index: 200, message_len: 1000 };
+
+        let mut packet = header.encode().to_vec();
+        packet.extend_from_slice(b"symbol");
+
+        let (decoded, symbol) = FecHeader::decode(&packet).unwrap();
+        assert_eq!(symbol, b"symbol");
+        assert_eq!((decoded.seq, decoded.num_source_symbols, decoded.symbol_index, decoded.message_len), (12, 4, 200, 1000));
+
+        assert!(FecHeader::decode(&packet[..FecHeader::LEN - 1]).is_none());
+    }
+
+    #[test]
+    fn fec_ack_round_trips() {
+        let Some(FecAck::Message { seq: 7 }) = FecAck::decode(&FecAck::Message { seq: 7 }.encode()) else {panic!("message ack changed");};
+        let Some(FecAck::Symbol { seq: 8, symbol_index: 9 }) = FecAck::decode(&FecAck::Symbol { seq: 8, symbol_index: 9 }.encode()) else {panic!("symbol ack changed");};
+
+        assert_eq!(FecAck::Message { seq: 0 }.encode().len(), FecAck::MIN_LEN);
+        assert!(FecAck::decode(&FecAck::Symbol { seq: 8, symbol_index: 9 }.encode()[..FecAck::MIN_LEN]).is_none());
+
+        let mut unknown = FecAck::Message { seq: 0 }.encode();
+        unknown[0] = 2;
+        assert!(FecAck::decode(&unknown).is_none());
+    }
+
+    #[test]
+    fn stream_and_request_headers_round_trip() {
+        let mut message = StreamHeader { stream_id: 1, chunk_index: 2, chunk_count: 3 }.encode().to_vec();
+        message.extend_from_slice(b"chunk");
+
+        let (header, chunk) = StreamHeader::decode(&message).unwrap();
+        assert_eq!((header.stream_id, header.chunk_index, header.chunk_count, chunk), (1, 2, 3, &b"chunk"[..]));
+
+        for response in [false, true] {
+            let mut message = RequestHeader { id: 42, response }.encode().to_vec();
+            message.extend_from_slice(b"payload");
+
+            let (header, payload) = RequestHeader::decode(&message).unwrap();
+            assert_eq!((header.id, header.response, payload), (42, response, &b"payload"[..]));
+        }
+
+        assert!(RequestHeader::decode(&[2; RequestHeader::LEN]).is_none());
+    }
+
+    #[test]
+    fn batched_messages_split_back_apart() {
+        let mut packet = Vec::new();
+        for message in [&b"one"[..], b"", b"three"] {
+            packet.extend_from_slice(&batched_prefix(message));
+            packet.extend_from_slice(message);
+        }
+
+        assert_eq!(split_batched(&packet), vec![&b"one"[..], b"", b"three"]);
+
+        // a message cut short at the end is dropped
+        assert_eq!(split_batched(&packet[..packet.len() - 1]), vec![&b"one"[..], b""]);
+    }
+
+    #[test]
+    fn checksum_detects_corruption() {
+        let mut packet = b"packet".to_vec();
+        packet.extend_from_slice(&checksum(&packet));
+        assert_eq!(verify_checksum(&packet), Some(&b"packet"[..]));
+
+        packet[0] ^= 1;
+        assert_eq!(verify_checksum(&packet), None);
+
+        // the crc32 check value
+        assert_eq!(checksum(b"123456789"), 0xCBF43926u32.to_be_bytes());
+    }
+}